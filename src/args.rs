@@ -1,37 +1,101 @@
+use std::path::PathBuf;
+
 use clap::{Command, Arg};
 
+use chip_8_emu::chip8::{Mode, MODE_NAMES};
+use chip_8_emu::cli;
+use chip_8_emu::fault::{FaultPolicy, POLICY_NAMES};
+use chip_8_emu::quirks::{Quirks, PRESET_NAMES};
+use chip_8_emu::trace::{TraceFilter, TraceFormat};
+
+// what the emulator was asked to do
+pub enum Action {
+    // run a ROM in a window
+    Run(Flags),
+    // serve the debug adapter protocol on stdin and stdout
+    Dap,
+    // write a listing of a ROM to a file, or stdout if no output path is given
+    Disasm { rom_path: String, output: Option<String>, mode: Mode },
+    // assemble Octo source into a ROM, and optionally a symbol map next to it
+    Asm { source_path: String, output: Option<String>, symbols: bool },
+    // report the first instruction where two traces differ
+    TraceDiff { paths: [String; 2], context: usize },
+}
+
 pub struct Flags {
     pub invert_colors: u8,
     pub rom_path: String,
-    pub hz: u64,
-    pub fg: Rgb,
-    pub bg: Rgb,
-    pub vol: f32
+    pub symbols_path: Option<PathBuf>,
+    pub ipf: u32,
+    // colors for each combination of XO-CHIP planes, the first two being bg and fg
+    pub palette: [Rgb; 4],
+    pub vol: f32,
+    pub quirks: Quirks,
+    pub mode: Mode,
+    pub fault_policy: FaultPolicy,
+    pub seed: Option<u64>,
+    pub debug: bool,
+    pub gdb_port: Option<u16>,
+    // watchpoints as the debugger's watch command takes them, --break-when conditions included
+    pub watches: Vec<String>,
+    pub trace_path: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub profile_path: Option<String>,
+    pub coverage_path: Option<String>,
+    pub rewind_depth: usize,
+    pub rewind_interval: u32
 }
 
+#[derive(Clone, Copy)]
 pub struct Rgb {
     pub r: u8,
     pub g: u8,
     pub b: u8
 }
 
-pub fn parse_args() -> Flags {
-    let m = Command::new(env!("CARGO_PKG_NAME"))
+pub fn parse_args() -> Action {
+    let m = cli::tool_args(Command::new(env!("CARGO_PKG_NAME"))
     .author(env!("CARGO_PKG_AUTHORS"))
     .version(env!("CARGO_PKG_VERSION"))
     .about("Interpretting Emulator for Chip-8")
+    .args_conflicts_with_subcommands(true)
+    .subcommand_negates_reqs(true)
+    .subcommand(Command::new("dap").about("Serve the Debug Adapter Protocol on stdin and stdout, for debugging ROMs from an editor. ROMs are given in the launch request."))
+    .subcommand(Command::new("disasm").about("Disassemble a ROM, following jumps, calls and skips from 0x200 to separate code from data.")
+        .arg(Arg::new("rom_path").required(true).help("The path of the ROM to disassemble."))
+        .arg(Arg::new("output").required(false).short('o').long("output").takes_value(true).help("The file to write the listing to. The listing is printed when not given."))
+        .arg(Arg::new("mode").required(false).short('m').long("mode").help("The instruction set to decode, instructions from other sets are treated as data.").possible_values(MODE_NAMES).default_value("chip8")))
+    .subcommand(Command::new("asm").about("Assemble Octo source into a ROM. Supports labels, :const, :alias, :org, :byte, register ops, if ... then, loop ... while ... again and byte literals.")
+        .arg(Arg::new("source_path").required(true).help("The path of the source to assemble."))
+        .arg(Arg::new("output").required(false).short('o').long("output").takes_value(true).help("The path of the ROM to write. Defaults to the source path with a '.ch8' extension."))
+        .arg(Arg::new("symbols").required(false).short('s').long("symbols").help("Also write a symbol map with a '.sym' extension next to the ROM, for the debug adapter to set breakpoints by line.")))
+    .subcommand(Command::new("trace-diff").about("Compare two traces written by --trace, in either form, and report the first cycle where the pc, registers, I, stack pointer or a memory write differ. Exits with 1 if they differ.")
+        .arg(Arg::new("trace_a").required(true).help("The path of the first trace."))
+        .arg(Arg::new("trace_b").required(true).help("The path of the second trace."))
+        .arg(Arg::new("context").required(false).short('C').long("context").help("The amount of records to show before and after the divergence.").default_value("5")))
 
     .arg(Arg::new("rom_path").required(true).help("The path of the ROM that is to be loaded into the emulator. If a '.state' file is loaded, the emulator will resume from that save state."))
     .arg(Arg::new("invert_colors").required(false).short('i').long("invert-colors").help("Invert colors of the screen of the emulator."))
-    .arg(Arg::new("hz").required(false).short('h').long("hz").help("The amount of loops that the emulator runs in one second.").default_value("500"))
+    .arg(Arg::new("ipf").required(false).short('c').long("ipf").help("The amount of instructions that the emulator runs in each 60hz frame.").default_value("8"))
     .arg(Arg::new("foreground_color").required(false).short('f').long("fg").help("The color in Hex that will be the foreground color.").default_value("FFFFFF"))
     .arg(Arg::new("background_color").required(false).short('b').long("bg").help("The color in Hex that will be the background color.").default_value("000000"))
+    .arg(Arg::new("on_fault").required(false).long("on-fault").help("What to do when the ROM crashes the emulated cpu: halt it, log the fault and continue, or ignore the fault. The same fault again in the next frame, e.g. with the program counter past the end of memory, halts it either way.").possible_values(POLICY_NAMES).default_value("halt"))
+    .arg(Arg::new("seed").required(false).short('s').long("seed").takes_value(true).help("Seed for the random number generator, runs with the same seed and inputs are reproducible. A random seed is used when not given."))
+    .arg(Arg::new("palette").required(false).short('p').long("palette").help("The 4 comma separated colors in Hex used for XO-CHIP planes, in the order: background, plane 1, plane 2, both planes. Overrides --fg and --bg.").default_value("000000,FFFFFF,AAAAAA,555555"))
     .arg(Arg::new("volume").required(false).short('v').long("volume").help("Volume of the beep as a float.").default_value("0.2"))
+    .arg(Arg::new("mode").required(false).short('m').long("mode").help("The instruction set to emulate, 'schip' enables SUPER-CHIP 1.1 instructions and the 128x64 hi-res mode.").possible_values(MODE_NAMES).default_value("chip8"))
+    .arg(Arg::new("quirks").required(false).short('q').long("quirks").help("The interpreter quirks preset to emulate. Defaults to 'modern', or 'schip' in SUPER-CHIP mode.").possible_values(PRESET_NAMES))
+    .arg(Arg::new("debug").required(false).short('d').long("debug").help("Start paused, with a debugger prompt on the terminal. Type 'help' at the prompt for a list of commands."))
+    .arg(Arg::new("gdb").required(false).long("gdb").takes_value(true).value_name("PORT").help("Start paused and wait for a GDB remote protocol connection on the given local port."))
+    .arg(Arg::new("rewind_depth").required(false).long("rewind-depth").help("The amount of snapshots kept for rewinding with Backspace, 0 disables rewinding.").default_value("600"))
+    .arg(Arg::new("rewind_interval").required(false).long("rewind-interval").help("The amount of frames between rewind snapshots.").default_value("1")))
     .before_help("
 Keybindings:
 
 - F5 for saving state.
 - F6 for loading state.
+- Hold Backspace to rewind.
 
 Keypad:        Keyboard:
 ---------      ---------
@@ -45,14 +109,66 @@ Keypad:        Keyboard:
 ---------      ---------")
     .get_matches();
 
-    return Flags {
+    if m.subcommand_matches("dap").is_some() {
+        return Action::Dap;
+    }
+    if let Some(m) = m.subcommand_matches("disasm") {
+        return Action::Disasm {
+            rom_path: m.value_of("rom_path").unwrap().to_string(),
+            output: m.value_of("output").map(|x| x.to_string()),
+            mode: Mode::from_name(m.value_of("mode").unwrap()).unwrap()
+        };
+    }
+    if let Some(m) = m.subcommand_matches("asm") {
+        return Action::Asm {
+            source_path: m.value_of("source_path").unwrap().to_string(),
+            output: m.value_of("output").map(|x| x.to_string()),
+            symbols: m.is_present("symbols")
+        };
+    }
+    if let Some(m) = m.subcommand_matches("trace-diff") {
+        return Action::TraceDiff {
+            paths: [m.value_of("trace_a").unwrap().to_string(), m.value_of("trace_b").unwrap().to_string()],
+            context: m.value_of("context").unwrap().parse::<usize>().unwrap()
+        };
+    }
+
+    let mode = Mode::from_name(m.value_of("mode").unwrap()).unwrap();
+
+    let fg = hex_to_rgb(u32::from_str_radix(m.value_of("foreground_color").unwrap(), 16).unwrap());
+    let bg = hex_to_rgb(u32::from_str_radix(m.value_of("background_color").unwrap(), 16).unwrap());
+    let mut palette = [bg; 4];
+    for (index, color) in m.value_of("palette").unwrap().split(',').take(4).enumerate() {
+        palette[index] = hex_to_rgb(u32::from_str_radix(color.trim(), 16).unwrap());
+    }
+    // --fg and --bg still apply when no palette is given
+    if m.occurrences_of("palette") == 0 {
+        palette[0] = bg;
+        palette[1] = fg;
+    }
+
+    return Action::Run(Flags {
         invert_colors: m.is_present("invert_colors") as u8,
         rom_path: m.value_of("rom_path").unwrap().to_string(),
-        hz: m.value_of("hz").unwrap().parse::<u64>().unwrap(),
-        fg: hex_to_rgb(u32::from_str_radix(m.value_of("foreground_color").unwrap(), 16).unwrap()),
-        bg: hex_to_rgb(u32::from_str_radix(m.value_of("background_color").unwrap(), 16).unwrap()),
-        vol: m.value_of("volume").unwrap().parse::<f32>().unwrap()
-    };
+        symbols_path: cli::symbols_path(&m),
+        ipf: m.value_of("ipf").unwrap().parse::<u32>().unwrap(),
+        palette,
+        vol: m.value_of("volume").unwrap().parse::<f32>().unwrap(),
+        quirks: m.value_of("quirks").map(|x| Quirks::from_preset(x).unwrap()).unwrap_or_else(|| mode.default_quirks()),
+        mode,
+        fault_policy: FaultPolicy::from_name(m.value_of("on_fault").unwrap()).unwrap(),
+        seed: m.value_of("seed").map(|x| x.parse::<u64>().unwrap()),
+        debug: m.is_present("debug"),
+        gdb_port: m.value_of("gdb").map(|x| x.parse::<u16>().unwrap()),
+        watches: cli::watches(&m),
+        trace_path: m.value_of("trace").map(|x| x.to_string()),
+        trace_format: cli::trace_format(&m),
+        trace_filter: cli::trace_filter(&m),
+        profile_path: m.value_of("profile").map(|x| x.to_string()),
+        coverage_path: m.value_of("coverage").map(|x| x.to_string()),
+        rewind_depth: m.value_of("rewind_depth").unwrap().parse::<usize>().unwrap(),
+        rewind_interval: m.value_of("rewind_interval").unwrap().parse::<u32>().unwrap()
+    });
 }
 
 fn hex_to_rgb(hex: u32) -> Rgb {
@@ -0,0 +1,569 @@
+// Assembles a subset of Octo into ROMs that load at 0x200.
+//
+// Supported: comments, labels (': name'), ':const', ':alias', ':org', ':byte', register ops, 'if ... then',
+// 'loop ... while ... again', calls by naming a label, and bytes written as numbers for sprites and other data.
+// As in Octo, a program that doesn't start with ': main' begins with a jump to it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::symbols::SymbolMap;
+
+const START: u16 = 0x200;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: u32,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub struct Program {
+    pub rom: Vec<u8>,
+    // labels and the address of each source line, with no source path set
+    pub symbols: SymbolMap,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    // the low 12 bits of the opcode at the address
+    Nnn,
+    // the 16 bit word at the address, after F000
+    Long,
+}
+
+// a reference to a label that wasn't defined yet, filled in once the whole source is read
+struct Fixup {
+    addr: u16,
+    name: String,
+    kind: FixupKind,
+    line: u32,
+}
+
+// a loop being assembled, the address 'again' jumps back to and the 'while' jumps to patch past its end
+struct Loop {
+    start: u16,
+    breaks: Vec<u16>,
+    line: u32,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(u32, &'a str)>,
+    pos: usize,
+    memory: Vec<u8>,
+    here: u16,
+    // one past the highest address written
+    end: u16,
+    // true until ': main' is found where the program starts, the jump to main at 0x200 is dropped then
+    main_jump: bool,
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, i64>,
+    aliases: BTreeMap<String, u8>,
+    fixups: Vec<Fixup>,
+    loops: Vec<Loop>,
+    lines: BTreeMap<u32, u16>,
+    // the line of the statement being assembled
+    line: u32,
+}
+
+fn error<T>(line: u32, message: String) -> Result<T, AsmError> {
+    Err(AsmError { line, message })
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    }
+    else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    }
+    else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<i64>().ok()?
+    }
+    else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(token: &str) -> Option<u8> {
+    let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// words that can't be used as names
+static KEYWORDS: [&str; 40] = [
+    "clear", "return", ";", "exit", "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right",
+    "jump", "jump0", "native", "sprite", "save", "load", "saveflags", "loadflags", "bcd", "delay", "buzzer", "pitch",
+    "plane", "audio", "i", "key", "-key", "random", "hex", "bighex", "long", "if", "then", "loop", "while", "again",
+    "==", "!=", "-", ":=",
+];
+
+impl<'a> Assembler<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut tokens = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            tokens.extend(code.split_whitespace().map(|token| (index as u32 + 1, token)));
+        }
+        Assembler {
+            tokens,
+            pos: 0,
+            memory: vec![0; 0x10000],
+            // 0x200 holds the jump to main
+            here: START + 2,
+            end: START + 2,
+            main_jump: true,
+            labels: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            fixups: Vec::new(),
+            loops: Vec::new(),
+            lines: BTreeMap::new(),
+            line: 1,
+        }
+    }
+
+    fn fail<T>(&self, message: String) -> Result<T, AsmError> {
+        error(self.line, message)
+    }
+
+    fn next(&mut self) -> Result<&'a str, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(&(line, token)) => {
+                self.pos += 1;
+                self.line = line;
+                Ok(token)
+            },
+            None => self.fail("unexpected end of source".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|&(_, token)| token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.fail(format!("expected '{}', found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), AsmError> {
+        if self.here as usize >= self.memory.len() - 1 {
+            return self.fail("program doesn't fit in memory".to_string());
+        }
+        self.lines.entry(self.line).or_insert(self.here);
+        self.memory[self.here as usize] = value;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn word(&mut self, value: u16) -> Result<(), AsmError> {
+        self.byte((value >> 8) as u8)?;
+        self.byte(value as u8)
+    }
+
+    fn name(&mut self) -> Result<&'a str, AsmError> {
+        let name = self.next()?;
+        if KEYWORDS.contains(&name) || name.starts_with(':') || parse_number(name).is_some() || self.is_register(name) {
+            return self.fail(format!("'{}' can't be used as a name", name));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) || self.aliases.contains_key(name) {
+            return self.fail(format!("'{}' is already defined", name));
+        }
+        Ok(name)
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        parse_register(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        match parse_register(token).or_else(|| self.aliases.get(token).copied()) {
+            Some(register) => Ok(register as u16),
+            None => self.fail(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    // a number or constant
+    fn value(&mut self, token: &str) -> Result<i64, AsmError> {
+        match parse_number(token).or_else(|| self.constants.get(token).copied()) {
+            Some(value) => Ok(value),
+            None => self.fail(format!("expected a number, found '{}'", token)),
+        }
+    }
+
+    fn immediate(&mut self, bits: u32) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let value = self.value(token)?;
+        // negative bytes are written as their two's complement
+        let min = if bits == 8 { -128 } else { 0 };
+        if value < min || value >= 1 << bits {
+            return self.fail(format!("{} doesn't fit in {} bits", token, bits));
+        }
+        Ok((value as u16) & ((1 << bits) - 1))
+    }
+
+    // an address for the low bits of the instruction at here, forward references to labels are filled in later
+    fn address(&mut self, kind: FixupKind) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let value = match self.labels.get(token) {
+            Some(&addr) => addr as i64,
+            None if parse_number(token).is_some() || self.constants.contains_key(token) => self.value(token)?,
+            None if self.is_register(token) || KEYWORDS.contains(&token) => return self.fail(format!("expected an address, found '{}'", token)),
+            None => {
+                let addr = if kind == FixupKind::Long { self.here + 2 } else { self.here };
+                self.fixups.push(Fixup { addr, name: token.to_string(), kind, line: self.line });
+                return Ok(0);
+            },
+        };
+        let max = if kind == FixupKind::Long { 0xFFFF } else { 0xFFF };
+        if !(0..=max).contains(&value) {
+            return self.fail(format!("address {} is out of range", token));
+        }
+        Ok(value as u16)
+    }
+
+    // a condition and the opcodes that skip when it is true and when it is false
+    fn condition(&mut self) -> Result<(u16, u16), AsmError> {
+        let x = self.register()? << 8;
+        let op = self.next()?;
+        match op {
+            "key" => Ok((0xE09E | x, 0xE0A1 | x)),
+            "-key" => Ok((0xE0A1 | x, 0xE09E | x)),
+            "==" | "!=" => {
+                let (equal, unequal) = if self.peek().is_some_and(|token| self.is_register(token)) {
+                    let y = self.register()? << 4;
+                    (0x5000 | x | y, 0x9000 | x | y)
+                }
+                else {
+                    let nn = self.immediate(8)?;
+                    (0x3000 | x | nn, 0x4000 | x | nn)
+                };
+                Ok(if op == "==" { (equal, unequal) } else { (unequal, equal) })
+            },
+            _ => self.fail(format!("expected '==', '!=', 'key' or '-key', found '{}'", op)),
+        }
+    }
+
+    fn assignment(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let y_op = match op {
+            ":=" => 0x0,
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xE,
+            _ => return self.fail(format!("expected an operator, found '{}'", op)),
+        };
+        if self.peek().is_some_and(|token| self.is_register(token)) {
+            let y = self.register()?;
+            return self.word(0x8000 | x << 8 | y << 4 | y_op);
+        }
+        match (op, self.peek()) {
+            (":=", Some("key")) => {
+                self.next()?;
+                self.word(0xF00A | x << 8)
+            },
+            (":=", Some("delay")) => {
+                self.next()?;
+                self.word(0xF007 | x << 8)
+            },
+            (":=", Some("random")) => {
+                self.next()?;
+                let nn = self.immediate(8)?;
+                self.word(0xC000 | x << 8 | nn)
+            },
+            (":=", _) => {
+                let nn = self.immediate(8)?;
+                self.word(0x6000 | x << 8 | nn)
+            },
+            ("+=", _) => {
+                let nn = self.immediate(8)?;
+                self.word(0x7000 | x << 8 | nn)
+            },
+            // subtracting a constant adds its negation
+            ("-=", _) => {
+                let nn = self.immediate(8)?;
+                self.word(0x7000 | x << 8 | (nn as u8).wrapping_neg() as u16)
+            },
+            _ => {
+                let token = self.next()?;
+                self.fail(format!("expected a register, found '{}'", token))
+            },
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        match token {
+            ":" => {
+                let name = self.name()?;
+                // a program starting with main doesn't need the jump to it
+                if name == "main" && self.main_jump && self.here == START + 2 && self.end == START + 2 {
+                    self.here = START;
+                    self.end = START;
+                    self.main_jump = false;
+                }
+                self.labels.insert(name.to_string(), self.here);
+            },
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = match self.labels.get(token) {
+                    Some(&addr) => addr as i64,
+                    None => self.value(token)?,
+                };
+                self.constants.insert(name.to_string(), value);
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.to_string(), register as u8);
+            },
+            ":org" => {
+                let token = self.next()?;
+                let addr = self.value(token)?;
+                if !(START as i64..0x10000).contains(&addr) {
+                    return self.fail(format!("address {} is out of range", token));
+                }
+                self.here = addr as u16;
+            },
+            ":byte" => {
+                let value = self.immediate(8)?;
+                self.byte(value as u8)?;
+            },
+            "clear" => self.word(0x00E0)?,
+            "return" | ";" => self.word(0x00EE)?,
+            "exit" => self.word(0x00FD)?,
+            "lores" => self.word(0x00FE)?,
+            "hires" => self.word(0x00FF)?,
+            "scroll-down" => {
+                let n = self.immediate(4)?;
+                self.word(0x00C0 | n)?;
+            },
+            "scroll-up" => {
+                let n = self.immediate(4)?;
+                self.word(0x00D0 | n)?;
+            },
+            "scroll-right" => self.word(0x00FB)?,
+            "scroll-left" => self.word(0x00FC)?,
+            "audio" => self.word(0xF002)?,
+            "plane" => {
+                let n = self.immediate(2)?;
+                self.word(0xF001 | n << 8)?;
+            },
+            "native" => {
+                let nnn = self.address(FixupKind::Nnn)?;
+                self.word(nnn)?;
+            },
+            "jump" => {
+                let nnn = self.address(FixupKind::Nnn)?;
+                self.word(0x1000 | nnn)?;
+            },
+            "jump0" => {
+                let nnn = self.address(FixupKind::Nnn)?;
+                self.word(0xB000 | nnn)?;
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.immediate(4)?;
+                self.word(0xD000 | x << 8 | y << 4 | n)?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.word(0x5000 | x << 8 | y << 4 | if token == "save" { 0x2 } else { 0x3 })?;
+                }
+                else {
+                    self.word(0xF000 | x << 8 | if token == "save" { 0x55 } else { 0x65 })?;
+                }
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.word(0xF075 | x << 8)?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.word(0xF085 | x << 8)?;
+            },
+            "bcd" => {
+                let x = self.register()?;
+                self.word(0xF033 | x << 8)?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let nn = match token {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.word(0xF000 | x << 8 | nn)?;
+            },
+            "i" => {
+                let op = self.next()?;
+                match (op, self.peek()) {
+                    ("+=", _) => {
+                        let x = self.register()?;
+                        self.word(0xF01E | x << 8)?;
+                    },
+                    (":=", Some("hex")) => {
+                        self.next()?;
+                        let x = self.register()?;
+                        self.word(0xF029 | x << 8)?;
+                    },
+                    (":=", Some("bighex")) => {
+                        self.next()?;
+                        let x = self.register()?;
+                        self.word(0xF030 | x << 8)?;
+                    },
+                    (":=", Some("long")) => {
+                        self.next()?;
+                        let nnnn = self.address(FixupKind::Long)?;
+                        self.word(0xF000)?;
+                        self.word(nnnn)?;
+                    },
+                    (":=", _) => {
+                        let nnn = self.address(FixupKind::Nnn)?;
+                        self.word(0xA000 | nnn)?;
+                    },
+                    _ => return self.fail(format!("expected ':=' or '+=', found '{}'", op)),
+                }
+            },
+            "if" => {
+                let (_, skip_unless) = self.condition()?;
+                self.expect("then")?;
+                self.word(skip_unless)?;
+            },
+            "loop" => {
+                // again and while jump with 1NNN, so both ends of the loop have to be in reach of it
+                if self.here > 0xFFF {
+                    return self.fail("'loop' is past 0xFFF".to_string());
+                }
+                self.loops.push(Loop { start: self.here, breaks: Vec::new(), line: self.line });
+            },
+            "while" => {
+                let (skip_if, _) = self.condition()?;
+                self.word(skip_if)?;
+                let here = self.here;
+                match self.loops.last_mut() {
+                    Some(open) => open.breaks.push(here),
+                    None => return self.fail("'while' outside of a loop".to_string()),
+                }
+                self.word(0x1000)?;
+            },
+            "again" => {
+                let open = match self.loops.pop() {
+                    Some(open) => open,
+                    None => return self.fail("'again' without a 'loop'".to_string()),
+                };
+                self.word(0x1000 | open.start)?;
+                if !open.breaks.is_empty() && self.here > 0xFFF {
+                    return error(open.line, "the end of the 'loop' is past 0xFFF".to_string());
+                }
+                for addr in open.breaks {
+                    self.memory[addr as usize + 1] = self.here as u8;
+                    self.memory[addr as usize] = 0x10 | (self.here >> 8) as u8;
+                }
+            },
+            _ if self.is_register(token) => {
+                let x = parse_register(token).or_else(|| self.aliases.get(token).copied()).unwrap() as u16;
+                self.assignment(x)?;
+            },
+            _ if parse_number(token).is_some() || self.constants.contains_key(token) => {
+                let value = self.value(token)?;
+                if !(-128..=255).contains(&value) {
+                    return self.fail(format!("{} doesn't fit in a byte", token));
+                }
+                self.byte(value as u8)?;
+            },
+            _ if token.starts_with(':') => return self.fail(format!("unknown directive '{}'", token)),
+            _ if KEYWORDS.contains(&token) => return self.fail(format!("unexpected '{}'", token)),
+            // naming a label calls it
+            _ => {
+                self.pos -= 1;
+                let nnn = self.address(FixupKind::Nnn)?;
+                self.word(0x2000 | nnn)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn assemble(mut self) -> Result<Program, AsmError> {
+        while self.pos < self.tokens.len() {
+            // statements are counted from their first token, which may be on a line of its own after a label
+            self.line = self.tokens[self.pos].0;
+            self.statement()?;
+        }
+        if let Some(open) = self.loops.first() {
+            return error(open.line, "'loop' without an 'again'".to_string());
+        }
+
+        for fixup in &self.fixups {
+            let addr = match self.labels.get(&fixup.name) {
+                Some(&addr) => addr,
+                None => return error(fixup.line, format!("undefined name '{}'", fixup.name)),
+            };
+            let at = fixup.addr as usize;
+            match fixup.kind {
+                FixupKind::Nnn if addr > 0xFFF => return error(fixup.line, format!("'{}' is past 0xFFF, use 'i := long'", fixup.name)),
+                FixupKind::Nnn => {
+                    self.memory[at] |= (addr >> 8) as u8;
+                    self.memory[at + 1] = addr as u8;
+                },
+                FixupKind::Long => {
+                    self.memory[at] = (addr >> 8) as u8;
+                    self.memory[at + 1] = addr as u8;
+                },
+            }
+        }
+
+        if self.main_jump {
+            let main = match self.labels.get("main") {
+                Some(&main) => main,
+                None => return error(1, "no ': main' label to start the program at".to_string()),
+            };
+            if main > 0xFFF {
+                return error(1, "'main' is past 0xFFF".to_string());
+            }
+            self.memory[START as usize] = 0x10 | (main >> 8) as u8;
+            self.memory[START as usize + 1] = main as u8;
+        }
+
+        Ok(Program {
+            rom: self.memory[START as usize..self.end as usize].to_vec(),
+            symbols: SymbolMap { source: None, labels: self.labels, lines: self.lines },
+        })
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    Assembler::new(source).assemble()
+}
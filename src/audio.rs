@@ -1,9 +1,19 @@
 use std::error::Error;
+use std::sync::{Arc, Mutex};
 
 use cpal::{traits::{HostTrait, DeviceTrait, StreamTrait}, SampleFormat, Stream, BuildStreamError};
 
+// XO-CHIP 1-bit audio pattern and the pitch it is played back at
+#[derive(Clone, Copy)]
+pub struct Pattern {
+    pub buffer: [u8; 16],
+    pub pitch: u8
+}
+
 pub struct Beeper {
-    pub stream: Stream
+    pub stream: Stream,
+    // the pattern being played, the plain 440hz beep is played when there is none
+    pattern: Arc<Mutex<Option<Pattern>>>
 }
 impl Beeper {
     pub fn new(vol: f32) -> Result<Self, Box<dyn Error>>  {
@@ -13,15 +23,20 @@ impl Beeper {
         let config = supported_config.config();
         let sample_format = supported_config.sample_format();
 
+        let pattern = Arc::new(Mutex::new(None));
         let streamres = match sample_format {
-            SampleFormat::F32 => run::<f32>(&device, &config, vol),
-            SampleFormat::I16 => run::<i16>(&device, &config, vol),
-            SampleFormat::U16 => run::<u16>(&device, &config, vol),
+            SampleFormat::F32 => run::<f32>(&device, &config, vol, pattern.clone()),
+            SampleFormat::I16 => run::<i16>(&device, &config, vol, pattern.clone()),
+            SampleFormat::U16 => run::<u16>(&device, &config, vol, pattern.clone()),
         }?;
         return Ok(Self {
-            stream: streamres
+            stream: streamres,
+            pattern
         });
     }
+    pub fn set_pattern(&self, pattern: Option<Pattern>) {
+        *self.pattern.lock().unwrap() = pattern;
+    }
     pub fn play(&self) {
         self.stream.play().unwrap();
     }
@@ -30,18 +45,32 @@ impl Beeper {
     }
 }
 
-pub fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig, vol: f32) -> Result<Stream, BuildStreamError>
+pub fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig, vol: f32, pattern: Arc<Mutex<Option<Pattern>>>) -> Result<Stream, BuildStreamError>
 where
     T: cpal::Sample,
 {
     let sample_rate = config.sample_rate.0 as f32;
     let channels = config.channels as usize;
 
-    // Produce a sinusoid of maximum amplitude.
+    // Produce a sinusoid of maximum amplitude, or play back the XO-CHIP pattern when one is loaded.
     let mut sample_clock = 0f32;
-    let mut next_value = move || {
-        sample_clock = (sample_clock + 1.0) % sample_rate;
-        ((sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin() / 6.0) * vol
+    let mut pattern_position = 0f32;
+    let mut next_value = move |pattern: Option<Pattern>| {
+        match pattern {
+            Some(pattern) => {
+                // the pattern is played at 4000 * 2 ^ ((pitch - 64) / 48) bits per second
+                let bitrate = 4000.0 * 2f32.powf((pattern.pitch as f32 - 64.0) / 48.0);
+                pattern_position = (pattern_position + bitrate / sample_rate) % 128.0;
+
+                let bit = pattern_position as usize;
+                let value = if pattern.buffer[bit / 8] >> (7 - bit % 8) & 1 == 1 { 1.0 } else { -1.0 };
+                (value / 6.0) * vol
+            },
+            None => {
+                sample_clock = (sample_clock + 1.0) % sample_rate;
+                ((sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin() / 6.0) * vol
+            }
+        }
     };
 
     let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
@@ -49,7 +78,8 @@ where
     let stream = device.build_output_stream(
         config,
         move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &mut next_value)
+            let pattern = *pattern.lock().unwrap();
+            write_data(data, channels, &mut || next_value(pattern))
         },
         err_fn,
     );
@@ -0,0 +1,258 @@
+// Runs a ROM without a window or audio, then dumps the display.
+// Exits with a non-zero code when the cpu faults, so that it can be used in CI.
+
+use std::io::{BufRead, Write};
+use std::process::exit;
+use std::sync::{Arc, Mutex, RwLock};
+
+use clap::{Command, Arg};
+
+use chip_8_emu::chip8::{Chip8, Mode, MODE_NAMES};
+use chip_8_emu::cli;
+use chip_8_emu::debugger::{self, Debugger, PROMPT};
+use chip_8_emu::coverage::Coverage;
+use chip_8_emu::dump;
+use chip_8_emu::fault::CpuFault;
+use chip_8_emu::fstools::load_rom_or_state;
+use chip_8_emu::gdbstub;
+use chip_8_emu::profile::Profile;
+use chip_8_emu::quirks::{Quirks, PRESET_NAMES};
+use chip_8_emu::symbols::SymbolMap;
+use chip_8_emu::trace::Trace;
+
+fn main() {
+    let m = cli::tool_args(Command::new("chip-8-headless")
+    .author(env!("CARGO_PKG_AUTHORS"))
+    .version(env!("CARGO_PKG_VERSION"))
+    .about("Runs a Chip-8 ROM without a display and dumps the screen")
+
+    .arg(Arg::new("rom_path").required(true).help("The path of the ROM that is to be loaded into the emulator. If a '.state' file is loaded, the emulator will resume from that save state."))
+    .arg(Arg::new("frames").required(false).short('n').long("frames").help("The amount of 60hz frames to run for.").default_value("60"))
+    .arg(Arg::new("cycles").required(false).long("cycles").takes_value(true).help("The amount of instructions to run for, overrides --frames. Timers still tick every --ipf instructions."))
+    .arg(Arg::new("ipf").required(false).short('c').long("ipf").help("The amount of instructions that the emulator runs in each 60hz frame.").default_value("8"))
+    .arg(Arg::new("mode").required(false).short('m').long("mode").help("The instruction set to emulate.").possible_values(MODE_NAMES).default_value("chip8"))
+    .arg(Arg::new("quirks").required(false).short('q').long("quirks").help("The interpreter quirks preset to emulate. Defaults to the mode's preset.").possible_values(PRESET_NAMES))
+    .arg(Arg::new("seed").required(false).short('s').long("seed").help("Seed for the random number generator.").default_value("0"))
+    .arg(Arg::new("debug").required(false).short('d').long("debug").help("Start paused, with a debugger prompt on the terminal. The display is dumped once the frames have run or the debugger quits."))
+    .arg(Arg::new("gdb").required(false).long("gdb").takes_value(true).value_name("PORT").help("Start paused and wait for a GDB remote protocol connection on the given local port. The display is dumped once the frames have run or gdb kills the program."))
+    .arg(Arg::new("output").required(false).short('o').long("output").takes_value(true).help("File to write the display to, as a PBM image if it ends in '.pbm' or as ASCII art otherwise. The display is printed as ASCII art when not given.")))
+    .get_matches();
+
+    let mode = Mode::from_name(m.value_of("mode").unwrap()).unwrap();
+    let ipf = m.value_of("ipf").unwrap().parse::<u32>().unwrap();
+
+    // setup cpu instance
+    let mut chip8inst = Chip8::new();
+    chip8inst.set_mode(mode);
+    chip8inst.quirks = m.value_of("quirks").map(|x| Quirks::from_preset(x).unwrap()).unwrap_or_else(|| mode.default_quirks());
+    chip8inst.seed_rng(m.value_of("seed").unwrap().parse::<u64>().unwrap());
+    // the symbols are loaded first, so that loading a state already shows the pc relative to them
+    let rom_path = m.value_of("rom_path").unwrap();
+    if let Some(path) = cli::symbols_path(&m) {
+        match SymbolMap::load(&path) {
+            Ok(symbols) => chip8inst.symbols = symbols,
+            Err(err) => {
+                eprintln!("Unable to load symbols from {}", err);
+                exit(2);
+            }
+        }
+    }
+    let rom_len = match load_rom_or_state(rom_path, &mut chip8inst) {
+        Ok(rom_len) => rom_len,
+        Err(err) => {
+            eprintln!("Unable to load {}: {}", rom_path, err);
+            exit(2);
+        }
+    };
+    if let Some(path) = m.value_of("trace") {
+        match Trace::create(path, cli::trace_format(&m), cli::trace_filter(&m)) {
+            Ok(trace) => chip8inst.trace = Some(trace),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                exit(2);
+            }
+        }
+    }
+    if let Some(path) = m.value_of("profile") {
+        match Profile::create(path) {
+            Ok(profile) => chip8inst.profile = Some(profile),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                exit(2);
+            }
+        }
+    }
+    if let Some(path) = m.value_of("coverage") {
+        match Coverage::create(path, rom_len) {
+            Ok(coverage) => chip8inst.coverage = Some(coverage),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                exit(2);
+            }
+        }
+    }
+
+    // run, whole frames first and then whatever is left of --cycles
+    let (frames, remainder) = match m.value_of("cycles") {
+        Some(cycles) => {
+            let cycles = cycles.parse::<u64>().unwrap();
+            (cycles / ipf.max(1) as u64, (cycles % ipf.max(1) as u64) as u32)
+        },
+        None => (m.value_of("frames").unwrap().parse::<u64>().unwrap(), 0)
+    };
+    let watches = cli::watches(&m);
+    let debugging = m.is_present("debug") || m.is_present("gdb") || !watches.is_empty();
+    let mut debugger = Debugger::new(ipf);
+    for spec in watches {
+        if let Err(err) = debugger.watch(&chip8inst, &spec) {
+            eprintln!("Invalid watchpoint '{}': {}", spec, err);
+            exit(2);
+        }
+    }
+    let mut result = Ok(());
+    if let Some(port) = m.value_of("gdb") {
+        // the debuggers report faults themselves
+        run_gdb(&mut chip8inst, debugger, frames, remainder, port.parse::<u16>().unwrap());
+    }
+    else if debugging {
+        // watchpoints alone run until one of them pauses, and end the run on a fault like running without them
+        let interactive = m.is_present("debug");
+        if !interactive {
+            debugger.resume();
+        }
+        result = run_debugger(&mut chip8inst, &mut debugger, frames, remainder, interactive);
+    }
+    else {
+        for _ in 0..frames {
+            result = chip8inst.run_frame(ipf);
+            if result.is_err() || chip8inst.halted {
+                break;
+            }
+        }
+    }
+    if result.is_ok() && !debugging {
+        for _ in 0..remainder {
+            result = chip8inst.single_cycle();
+            if result.is_err() {
+                break;
+            }
+        }
+    }
+
+    if let Some(trace) = &mut chip8inst.trace {
+        if let Err(err) = trace.flush() {
+            eprintln!("Unable to write the trace: {}", err);
+            exit(2);
+        }
+    }
+    if let Some(profile) = &chip8inst.profile {
+        if let Err(err) = profile.write() {
+            eprintln!("Unable to write the profile: {}", err);
+            exit(2);
+        }
+    }
+    if let Some(coverage) = &chip8inst.coverage {
+        if let Err(err) = coverage.write() {
+            eprintln!("Unable to write the coverage: {}", err);
+            exit(2);
+        }
+    }
+
+    // dump display
+    match m.value_of("output") {
+        Some(path) => {
+            let contents = if path.ends_with(".pbm") { dump::pbm(&chip8inst) } else { dump::ascii(&chip8inst) };
+            if let Err(err) = std::fs::write(path, contents) {
+                eprintln!("Unable to write {}: {}", path, err);
+                exit(2);
+            }
+        },
+        None => print!("{}", dump::ascii(&chip8inst))
+    }
+
+    if let Err(fault) = result {
+        eprintln!("CPU fault: {}", fault.describe(&chip8inst.symbols));
+        exit(1);
+    }
+}
+
+// runs frames under the debugger, reading commands from stdin whenever it is paused
+// faults pause the debugger in an interactive session, and end the run otherwise
+fn run_debugger(chip8: &mut Chip8, debugger: &mut Debugger, frames: u64, remainder: u32, interactive: bool) -> Result<(), CpuFault> {
+    let mut lines = std::io::stdin().lock().lines();
+    if debugger.paused {
+        println!("Paused, type 'help' for a list of commands.\n{}", debugger::stop_report(chip8, debugger));
+    }
+
+    let mut frame = 0;
+    while frame < frames_with_remainder(frames, remainder) && !debugger.quit {
+        if debugger.paused {
+            print!("{}", PROMPT);
+            let _ = std::io::stdout().flush();
+            match lines.next() {
+                Some(Ok(line)) => {
+                    let output = debugger.command(chip8, &line);
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                },
+                _ => break
+            }
+            continue;
+        }
+
+        let result = if frame < frames { debugger.run_frame(chip8) } else { debugger.run_cycles(chip8, remainder) };
+        match result {
+            Ok(false) => frame += 1,
+            Ok(true) => println!("{}\n{}", debugger.stop_message(chip8), debugger::stop_report(chip8, debugger)),
+            Err(fault) if !interactive => return Err(fault),
+            Err(fault) => println!("CPU fault: {}, paused.\n{}", fault.describe(&chip8.symbols), debugger::stop_report(chip8, debugger)),
+        }
+    }
+    // a step at the prompt can fault too
+    match debugger.fault {
+        Some(fault) if !interactive => Err(fault),
+        _ => Ok(()),
+    }
+}
+
+// runs frames while gdb controls the debugger from another thread
+fn run_gdb(chip8: &mut Chip8, debugger: Debugger, frames: u64, remainder: u32, port: u16) {
+    let chip8arc = Arc::new(RwLock::new(std::mem::take(chip8)));
+    let debuggerarc = Arc::new(Mutex::new(debugger));
+
+    let gdbchip8 = chip8arc.clone();
+    let gdbdebugger = debuggerarc.clone();
+    println!("Waiting for gdb on port {}", port);
+    std::thread::spawn(move || {
+        if let Err(err) = gdbstub::listen(port, gdbchip8, gdbdebugger) {
+            eprintln!("gdb connection failed: {}", err);
+        }
+    });
+
+    let mut frame = 0;
+    while frame < frames_with_remainder(frames, remainder) {
+        let paused = {
+            let mut chip8 = chip8arc.write().unwrap();
+            let mut debugger = debuggerarc.lock().unwrap();
+            if debugger.quit {
+                break;
+            }
+            let result = if frame < frames { debugger.run_frame(&mut chip8) } else { debugger.run_cycles(&mut chip8, remainder) };
+            if let Ok(false) = result {
+                frame += 1;
+            }
+            debugger.paused
+        };
+        if paused {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    *chip8 = std::mem::take(&mut *chip8arc.write().unwrap());
+}
+
+// the frames to run under a debugger, the remainder of --cycles runs as a last and shorter frame
+fn frames_with_remainder(frames: u64, remainder: u32) -> u64 {
+    if remainder > 0 { frames + 1 } else { frames }
+}
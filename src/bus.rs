@@ -0,0 +1,37 @@
+// The memory traffic of the interpreter, for tools that observe or intercept it without changing the opcodes.
+//
+// Every byte the cpu fetches as part of an instruction, reads as data (sprites, FX65, 5XY3 and F002) and writes
+// (FX33, FX55 and 5XY2) goes through the bus installed with Chip8::set_bus, or FlatMemory when there is none. The
+// bus is handed the machine's memory on every access, so a hook can count accesses and pass them on, refuse writes,
+// or answer reads of addresses that aren't backed by memory at all, e.g. for a memory mapped device. Debuggers,
+// save states and the rest of the tooling look at chip8.memory directly and don't go through the bus.
+
+// None or false for an access the bus can't serve, which faults with MemoryOutOfBounds, or PcOutOfBounds for an
+// opcode fetch
+pub trait Bus {
+    // a byte of an instruction: the opcode at pc, the address of the XO-CHIP long load, and the first bytes of an
+    // instruction that is skipped, to tell how long it is
+    fn fetch(&mut self, memory: &[u8], addr: usize) -> Option<u8> {
+        memory.get(addr).copied()
+    }
+
+    fn read(&mut self, memory: &[u8], addr: usize) -> Option<u8> {
+        memory.get(addr).copied()
+    }
+
+    fn write(&mut self, memory: &mut [u8], addr: usize, value: u8) -> bool {
+        match memory.get_mut(addr) {
+            Some(byte) => {
+                *byte = value;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// plain memory, 4 KiB or 64 KiB in XO-CHIP mode, with nothing outside of it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FlatMemory;
+
+impl Bus for FlatMemory {}
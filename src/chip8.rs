@@ -1,8 +1,40 @@
-use crate::opcode_parser::parse_op;
+use crate::bus::{Bus, FlatMemory};
+use crate::coverage::Coverage;
+use crate::fault::CpuFault;
+use crate::history::History;
+use crate::instruction::Instruction;
+use crate::observer::{Event, Observer};
+use crate::opcode_parser::execute;
+use crate::profile::Profile;
+use crate::quirks::Quirks;
+use crate::rng::SplitMix64;
+use crate::symbols::SymbolMap;
+use crate::trace::Trace;
+
+use std::io;
+
+use rand::{Rng, RngCore};
 
 // 0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
-// 0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
+// 0x000-0x04F - Used for the built in 4x5 pixel font set (0-F)
+// 0x050-0x0EF - Used for the built in 8x10 pixel SUPER-CHIP font set (0-F)
 // 0x200-0xFFF - Program ROM and work RAM
+// 0x1000-0xFFFF - Extended program ROM and work RAM (XO-CHIP only)
+
+pub const MEMORY_SIZE: usize = 0x1000;
+pub const XO_MEMORY_SIZE: usize = 0x10000;
+
+pub const BIG_FONT_ADDR: u16 = 0x50;
+
+// the version of the saved fields, states of an earlier version are refused instead of loaded as garbage
+// 1 - memory and display sized by the mode, hi-res, planes, quirks, the rng and the XO-CHIP audio
+pub const STATE_VERSION: u32 = 1;
+
+// display resolutions, lo-res is the only one available outside of SUPER-CHIP mode
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
 static FONTSET: [u8; 80] = [
 	0xF0, 0x90, 0x90, 0x90, 0xF0,		// 0
@@ -23,12 +55,68 @@ static FONTSET: [u8; 80] = [
 	0xF0, 0x80, 0xF0, 0x80, 0x80		// F
 ];
 
+static BIG_FONTSET: [u8; 160] = [
+	0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,		// 0
+	0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,		// 1
+	0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,		// 2
+	0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,		// 3
+	0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,		// 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,		// 5
+	0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,		// 6
+	0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,		// 7
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,		// 8
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C,		// 9
+	0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,		// A
+	0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,		// B
+	0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,		// C
+	0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,		// D
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,		// E
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0		// F
+];
+
+// the instruction set the interpreter understands
+#[derive(Savefile, Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+pub static MODE_NAMES: [&str; 3] = ["chip8", "schip", "xochip"];
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Mode::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Mode::SuperChip),
+            "xochip" | "xo-chip" => Some(Mode::XoChip),
+            _ => None
+        }
+    }
+
+    // the quirks preset used when none is given explicitly
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Mode::Chip8 => Quirks::modern(),
+            Mode::SuperChip => Quirks::schip(),
+            Mode::XoChip => Quirks::xochip(),
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Mode::XoChip => XO_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+}
+
 #[derive(Savefile)]
 pub struct Chip8 {
     
     // current opcode
     pub opcode: u16,
-    pub memory: [u8; 4096],
+    pub memory: Vec<u8>,
 
     // V registers
     pub vregisters: [u8; 16],
@@ -43,20 +131,96 @@ pub struct Chip8 {
 
     // stack used to remember the current location before a jump is performed.
     pub jumpstack: [u16; 16],
-    // system has 16 levels of stack, to remember which level, a pointer is used.
+    // system has 16 levels of stack, to remember which level, a pointer to the next free level is used.
     pub stackpointer: u16,
 
     // hex based keypad 0x0-0xF
     pub keystate: [u8; 16],
+    // key that FX0A saw pressed and is waiting to be released
+    pub waiting_key: Option<u8>,
+
+    // pixels are stored column by column, (x, y) is at x * height + y
+    // each pixel is a bitmask of the planes it is set in, bit 0 being the first plane
+    pub display: Vec<u8>,
+    // XO-CHIP bitmask of the planes that drawing, clearing and scrolling affect
+    pub planes: u8,
+    // SUPER-CHIP 128x64 high resolution mode
+    pub hires: bool,
+    // what a new display is filled with when the resolution changes, 1 while the colors are inverted
+    // this is a setting of the frontend and isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub blank_pixel: u8,
+
+    pub mode: Mode,
+    // SUPER-CHIP RPL user flags used by FX75/FX85
+    pub rpl_flags: [u8; 16],
+    // set by the SUPER-CHIP 00FD exit instruction, nothing is executed afterwards
+    pub halted: bool,
+
+    // XO-CHIP 1-bit 128 sample audio pattern loaded by F002, the plain buzzer is used until one is loaded
+    pub audio_pattern: Option<[u8; 16]>,
+    // XO-CHIP audio pattern playback pitch set by FX3A
+    pub pitch: u8,
+
+    // interpreter behaviours that differ between chip-8 implementations
+    pub quirks: Quirks,
+
+    // set on every 60hz timer tick, consumed by DXYN when the display wait quirk is enabled
+    pub vblank_interrupt: bool,
 
-    pub display: [u8; 2048],
+    // random number source for CXNN, saved with the state so that it restores into the same future
+    pub rng: SplitMix64,
+    // replaces rng when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub custom_rng: Option<Box<dyn RngCore + Send + Sync>>,
+
+    // sees the memory traffic of the interpreter in place of FlatMemory when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub bus: Option<Box<dyn Bus + Send + Sync>>,
+
+    // told about draws, sound, key waits, calls and faults when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub observer: Option<Box<dyn Observer + Send + Sync>>,
+    // whether the last instruction was FX0A waiting for a key, so that the observer hears of a wait once
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    key_wait: bool,
+
+    // logs every executed instruction when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub trace: Option<Trace>,
+
+    // records how to undo every executed instruction when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub history: Option<History>,
+
+    // counts executed instructions by address, opcode and subroutine when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub profile: Option<Profile>,
+
+    // marks the addresses executed, read and written when set, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub coverage: Option<Coverage>,
+
+    // labels of the loaded ROM, for showing addresses relative to them, this isn't saved with the state
+    #[savefile_ignore]
+    #[savefile_introspect_ignore]
+    pub symbols: SymbolMap,
 }
 
 impl Chip8 {
     pub fn new() -> Self {
         let mut chip8 = Chip8 {
             opcode: 0,
-            memory: [0; 4096],
+            memory: vec![0; MEMORY_SIZE],
             vregisters: [0; 16],
             i: 0,
             pc: 0x200,
@@ -65,35 +229,336 @@ impl Chip8 {
             jumpstack: [0; 16],
             stackpointer: 0,
             keystate: [0; 16],
-            display: [0; 2048]
+            waiting_key: None,
+            display: vec![0; LORES_WIDTH * LORES_HEIGHT],
+            planes: 1,
+            hires: false,
+            blank_pixel: 0,
+            mode: Mode::Chip8,
+            rpl_flags: [0; 16],
+            halted: false,
+            audio_pattern: None,
+            pitch: 64,
+            quirks: Quirks::default(),
+            vblank_interrupt: false,
+            rng: SplitMix64::new(rand::thread_rng().gen()),
+            custom_rng: None,
+            bus: None,
+            observer: None,
+            key_wait: false,
+            trace: None,
+            history: None,
+            profile: None,
+            coverage: None,
+            symbols: SymbolMap::default()
         };
         chip8.load_fontset();
         chip8
     }
     fn load_fontset(&mut self) {
-        for i in 0..80 {
-            self.memory[i] = FONTSET[i];
+        self.memory[..80].copy_from_slice(&FONTSET);
+        self.memory[BIG_FONT_ADDR as usize..BIG_FONT_ADDR as usize + 160].copy_from_slice(&BIG_FONTSET);
+    }
+    // makes CXNN reproducible, the same seed always gives the same sequence of random numbers
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = SplitMix64::new(seed);
+        self.custom_rng = None;
+    }
+    // uses a custom random number source for CXNN, or goes back to the seedable one with None
+    pub fn set_rng(&mut self, rng: Option<Box<dyn RngCore + Send + Sync>>) {
+        self.custom_rng = rng;
+    }
+    pub fn random_byte(&mut self) -> u8 {
+        match &mut self.custom_rng {
+            Some(rng) => rng.gen(),
+            None => self.rng.gen()
+        }
+    }
+
+    // replaces the machine with a state restored from a save state or a rewind snapshot
+    // keys held down now, the blank pixel, the bus, the observer, the trace, the profile, the coverage, the symbols
+    // and a custom rng aren't part of the state and carry over, the history can't undo into the restored state so it
+    // starts over
+    // fails if the memory, the display or the stack pointer don't fit the state's mode, leaving the machine as it was
+    pub fn restore_from(&mut self, mut state: Chip8) -> io::Result<()> {
+        if state.memory.len() != state.mode.memory_size() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the state has {} bytes of memory, its mode has {}", state.memory.len(), state.mode.memory_size())));
+        }
+        if state.display.len() != state.display_width() * state.display_height() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the state's display has {} pixels, not {}x{}", state.display.len(), state.display_width(), state.display_height())));
+        }
+        if state.stackpointer as usize > state.jumpstack.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the state's stack pointer {} is past the stack", state.stackpointer)));
+        }
+        let sounding = self.sound_timer > 0;
+        state.keystate = self.keystate;
+        state.blank_pixel = self.blank_pixel;
+        state.bus = self.bus.take();
+        state.observer = self.observer.take();
+        state.trace = self.trace.take();
+        state.profile = self.profile.take();
+        state.coverage = self.coverage.take();
+        state.symbols = std::mem::take(&mut self.symbols);
+        state.custom_rng = self.custom_rng.take();
+        state.history = self.history.take().map(|mut history| {
+            history.clear();
+            history
+        });
+        *self = state;
+        // the observer hears of the display and the sound changing with the state
+        if self.observer.is_some() {
+            self.notify(Event::Drawn);
+            self.notify_sound(sounding);
+        }
+        Ok(())
+    }
+
+    // routes the memory accesses of the interpreter through a bus, or goes back to plain memory with None
+    pub fn set_bus(&mut self, bus: Option<Box<dyn Bus + Send + Sync>>) {
+        self.bus = bus;
+    }
+    pub fn fetch_byte(&mut self, addr: usize) -> Option<u8> {
+        match &mut self.bus {
+            Some(bus) => bus.fetch(&self.memory, addr),
+            None => FlatMemory.fetch(&self.memory, addr)
+        }
+    }
+    pub fn read_byte(&mut self, addr: usize) -> Option<u8> {
+        match &mut self.bus {
+            Some(bus) => bus.read(&self.memory, addr),
+            None => FlatMemory.read(&self.memory, addr)
+        }
+    }
+    // false if the bus couldn't write the byte
+    pub fn write_byte(&mut self, addr: usize, value: u8) -> bool {
+        match &mut self.bus {
+            Some(bus) => bus.write(&mut self.memory, addr, value),
+            None => FlatMemory.write(&mut self.memory, addr, value)
+        }
+    }
+
+    // tells an observer about events from now on, or stops with None
+    pub fn set_observer(&mut self, observer: Option<Box<dyn Observer + Send + Sync>>) {
+        self.observer = observer;
+    }
+    // the observer is taken out while it reads the state
+    fn notify(&mut self, event: Event) {
+        if let Some(mut observer) = self.observer.take() {
+            observer.notify(self, event);
+            self.observer = Some(observer);
+        }
+    }
+
+    // switches the instruction set, resizing memory for XO-CHIP
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.memory.resize(mode.memory_size(), 0);
+    }
+    // fails if the program doesn't fit in the memory after 0x200
+    pub fn load_program(&mut self, program: &[u8]) -> io::Result<()> {
+        let space = self.memory.len() - 0x200;
+        if program.len() > space {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the ROM is {} bytes, only {} fit in memory after 0x200", program.len(), space)));
+        }
+        self.memory[0x200..0x200 + program.len()].copy_from_slice(program);
+        Ok(())
+    }
+
+
+    pub fn display_width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+    pub fn display_height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { LORES_HEIGHT }
+    }
+    // switches between lo-res and hi-res, clearing the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = vec![self.blank_pixel; self.display_width() * self.display_height()];
+    }
+
+    // moves the selected planes by dx, dy pixels, filling the space left behind with 0
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.display_width() as isize;
+        let height = self.display_height() as isize;
+        let planes = self.planes;
+        let old = self.display.clone();
+
+        for x in 0..width {
+            for y in 0..height {
+                let (srcx, srcy) = (x - dx, y - dy);
+                let src = if srcx >= 0 && srcx < width && srcy >= 0 && srcy < height {
+                    old[(srcx * height + srcy) as usize]
+                }
+                else {
+                    0
+                };
+                let pixel = &mut self.display[(x * height + y) as usize];
+                *pixel = (*pixel & !planes) | (src & planes);
+            }
         }
     }
-    pub fn load_program(&mut self, program: &Vec<u8>) {
-        for i in 0..program.len() {
-            self.memory[0x200 + i] = program[i];
+
+    pub fn single_cycle(&mut self) -> Result<(), CpuFault> {
+        if self.halted {
+            return Ok(());
+        }
+
+        // the history is taken out while it records the instruction
+        match self.history.take() {
+            Some(mut history) => {
+                let result = history.record(self, Chip8::cycle);
+                self.history = Some(history);
+                result
+            },
+            None => self.cycle(),
         }
     }
 
+    // undoes up to n instructions from the history, returns how many were undone
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let mut history = match self.history.take() {
+            Some(history) => history,
+            None => return 0,
+        };
+        let undone = (0..n).take_while(|_| history.undo(self)).count();
+        self.history = Some(history);
+        undone
+    }
+
+    // undoes instructions until condition holds for the state, false if the history ran out first
+    pub fn run_back_until(&mut self, mut condition: impl FnMut(&Chip8) -> bool) -> bool {
+        let mut history = match self.history.take() {
+            Some(history) => history,
+            None => return false,
+        };
+        let mut found = false;
+        while history.undo(self) {
+            if condition(self) {
+                found = true;
+                break;
+            }
+        }
+        self.history = Some(history);
+        found
+    }
 
-    pub fn single_cycle(&mut self) {
+    fn cycle(&mut self) -> Result<(), CpuFault> {
+        let sounding = self.sound_timer > 0;
+        let result = self.fetch_execute();
+        if self.observer.is_some() {
+            self.observe(result, sounding);
+        }
+        result.map(|_| ())
+    }
+
+    // the events an instruction caused, sounding being whether the sound timer ran before it
+    fn observe(&mut self, result: Result<(u16, Instruction), CpuFault>, sounding: bool) {
+        let (pc, instruction) = match result {
+            Ok(executed) => executed,
+            Err(CpuFault::UnknownOpcode { pc, opcode }) => return self.notify(Event::UnknownOpcode { pc, opcode }),
+            Err(fault) => return self.notify(Event::Fault(fault)),
+        };
+        // instructions of other instruction sets do nothing
+        let waiting = self.pc == pc;
+        let event = match instruction {
+            Instruction::Call(addr) => Some(Event::Call { pc, addr }),
+            Instruction::Return => Some(Event::Return { pc, addr: self.pc }),
+            _ if self.draw_waiting(pc, instruction) => None,
+            Instruction::Clear | Instruction::Draw { .. } | Instruction::ScrollDown(_) | Instruction::ScrollUp(_) |
+            Instruction::ScrollRight | Instruction::ScrollLeft | Instruction::Lores | Instruction::Hires
+                if instruction.supported_by(self.mode) => Some(Event::Drawn),
+            Instruction::WaitKey(x) if waiting && !self.key_wait => Some(Event::KeyWait(x)),
+            _ => None,
+        };
+        self.key_wait = matches!(instruction, Instruction::WaitKey(_)) && waiting;
+        if let Some(event) = event {
+            self.notify(event);
+        }
+        self.notify_sound(sounding);
+    }
+
+    // tells the observer the sound started or stopped, if it did since sounding was taken
+    fn notify_sound(&mut self, sounding: bool) {
+        match (sounding, self.sound_timer > 0) {
+            (false, true) => self.notify(Event::SoundStarted),
+            (true, false) => self.notify(Event::SoundStopped),
+            _ => {}
+        }
+    }
+
+    // whether the instruction that ran at pc is a draw waiting for the vertical blank, which runs again and hasn't
+    // drawn or read its sprite yet
+    pub fn draw_waiting(&self, pc: u16, instruction: Instruction) -> bool {
+        matches!(instruction, Instruction::Draw { .. }) && self.pc == pc
+    }
+
+    // the address and instruction that ran
+    fn fetch_execute(&mut self) -> Result<(u16, Instruction), CpuFault> {
         // fetch
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16);
-        self.pc += 2;
+        let (high, low) = match (self.fetch_byte(self.pc as usize), self.fetch_byte(self.pc as usize + 1)) {
+            (Some(high), Some(low)) => (high, low),
+            _ => return Err(CpuFault::PcOutOfBounds { pc: self.pc }),
+        };
+        self.opcode = (high as u16) << 8 | low as u16;
+        self.pc = self.pc.wrapping_add(2);
+
+        // decode
+        let pc = self.pc.wrapping_sub(2);
+        let instruction = Instruction::decode(self.opcode).map_err(|err| CpuFault::UnknownOpcode { pc, opcode: err.opcode })?;
 
-        // decode - none
         // execute
-        self.execute();
+        let i = self.i;
+        self.execute(instruction)?;
+
+        // trace, profile and coverage, taken out while they read the state
+        if let Some(mut trace) = self.trace.take() {
+            trace.record(self, pc, instruction, i);
+            self.trace = Some(trace);
+        }
+        if let Some(mut profile) = self.profile.take() {
+            profile.record(self, pc, instruction);
+            self.profile = Some(profile);
+        }
+        if let Some(mut coverage) = self.coverage.take() {
+            coverage.record(self, pc, instruction, i);
+            self.coverage = Some(coverage);
+        }
+        Ok((pc, instruction))
+    }
+    pub fn execute(&mut self, instruction: Instruction) -> Result<(), CpuFault> {
+        execute(self, instruction)
+    }
+
+    // runs one 60hz frame: cycles_per_frame instructions followed by a timer tick
+    // a fault ends the frame early, the timers are still ticked so that time keeps moving
+    pub fn run_frame(&mut self, cycles_per_frame: u32) -> Result<(), CpuFault> {
+        let mut result = Ok(());
+        for _ in 0..cycles_per_frame {
+            if self.halted {
+                break;
+            }
+            result = self.single_cycle();
+            if result.is_err() {
+                break;
+            }
+        }
+        self.tick_timers();
+        result
+    }
 
-        // store
+    // decrements the timers and signals the vertical blank, called at 60hz
+    pub fn tick_timers(&mut self) {
+        let sounding = self.sound_timer > 0;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.vblank_interrupt = true;
+        self.notify_sound(sounding);
     }
-    fn execute(&mut self) {
-        parse_op(self);
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
     }
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+// Command line arguments shared by the windowed emulator and the headless runner: the symbol map, watchpoints,
+// and the trace, profile and coverage tools.
+//
+// Both commands add them with tool_args, and read them back with the functions below once clap has validated them.
+
+use std::path::PathBuf;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::symbols::SymbolMap;
+use crate::trace::{TraceFilter, TraceFormat, FORMAT_NAMES};
+
+pub fn tool_args(command: Command) -> Command {
+    command
+    .arg(Arg::new("break_when").required(false).long("break-when").takes_value(true).multiple_occurrences(true).value_name("EXPR").help("Pause at the debugger prompt once a condition over the registers and memory becomes true, e.g. 'pc == 0x2A0 && v3 > 10', 'i in 0x300..0x320' or 'mem[i] == 0'. Can be given more than once."))
+    .arg(Arg::new("watch").required(false).long("watch").takes_value(true).multiple_occurrences(true).value_name("SPEC").help("Pause at the debugger prompt when an instruction reads or writes memory in a range, e.g. 'write 0x300..0x320' or 'read sprite', or changes a register, e.g. 'v3'. Can be given more than once."))
+    .arg(Arg::new("symbols").required(false).long("symbols").takes_value(true).value_name("FILE").help("A symbol map to show addresses relative to labels with, e.g. main+0x4, and to set breakpoints on labels. Defaults to a file next to the ROM with a '.sym' extension, if there is one."))
+    .arg(Arg::new("trace").required(false).long("trace").takes_value(true).value_name("FILE").help("Log every executed instruction to a file, with the cycle, pc, opcode, registers, I and stack pointer after it."))
+    .arg(Arg::new("trace_format").required(false).long("trace-format").help("The form of the trace, a line of text per instruction or compact binary records for long runs.").possible_values(FORMAT_NAMES).default_value("text"))
+    .arg(Arg::new("trace_pc").required(false).long("trace-pc").takes_value(true).value_name("START-END").validator(|x| TraceFilter::parse_pcs(x).ok_or("expected a range of addresses like 0x200-0x2FF")).help("Only trace instructions at addresses in the range, e.g. 0x200-0x2FF."))
+    .arg(Arg::new("trace_cycles").required(false).long("trace-cycles").takes_value(true).value_name("FIRST-END").validator(|x| TraceFilter::parse_cycles(x).ok_or("expected a range of cycles like 1000-2000")).help("Only trace the instructions from cycle FIRST up to END, either end can be left out, e.g. 1000-2000 or 1000-."))
+    .arg(Arg::new("profile").required(false).long("profile").takes_value(true).value_name("FILE").help("Count executed instructions by address, opcode and subroutine, and write a report of the hot spots, the instruction mix and the call graph to a file on exit. Folded stacks for flame graph tools are written next to it with a '.folded' extension."))
+    .arg(Arg::new("coverage").required(false).long("coverage").takes_value(true).value_name("FILE").help("Mark the addresses executed as opcodes, read as data and written, and write a map of them to a file on exit, with how much of the ROM was used and the code that was modified."))
+}
+
+// --symbols, or the map next to the ROM given as rom_path
+pub fn symbols_path(m: &ArgMatches) -> Option<PathBuf> {
+    m.value_of("symbols").map(PathBuf::from).or_else(|| SymbolMap::path_for_rom(m.value_of("rom_path")?))
+}
+
+// watchpoints as the debugger's watch command takes them, --break-when conditions included
+pub fn watches(m: &ArgMatches) -> Vec<String> {
+    m.values_of("break_when").into_iter().flatten().map(|x| format!("if {}", x))
+        .chain(m.values_of("watch").into_iter().flatten().map(|x| x.to_string()))
+        .collect()
+}
+
+pub fn trace_format(m: &ArgMatches) -> TraceFormat {
+    TraceFormat::from_name(m.value_of("trace_format").unwrap()).unwrap()
+}
+
+pub fn trace_filter(m: &ArgMatches) -> TraceFilter {
+    TraceFilter {
+        pcs: m.value_of("trace_pc").and_then(TraceFilter::parse_pcs),
+        cycles: m.value_of("trace_cycles").and_then(TraceFilter::parse_cycles).unwrap_or((0, u64::MAX)),
+    }
+}
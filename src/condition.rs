@@ -0,0 +1,284 @@
+// A small expression language over the machine state, for pausing when a condition holds.
+//
+// Expressions are made of numbers (decimal, or hex and binary with a 0x or 0b prefix), the registers v0-vf, i, pc,
+// sp, dt and st, memory bytes as mem[<expr>] and labels of the loaded ROM, which stand for their address.
+// Operators, from the loosest binding:
+//   ||  &&  == != < <= > >= and <expr> in <start>..<end>  + - &  and the unary ! and -
+// Comparisons don't chain and ranges include their start and exclude their end. A condition holds when its value
+// isn't 0, comparisons being 1 when they are true, e.g. "pc == 0x2A0 && v3 > 10", "i in 0x300..0x320" or "mem[i + 1] == 0".
+
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::symbols::SymbolMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    // value in start..end
+    In(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, chip8: &Chip8) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => match *register {
+                Register::V(x) => chip8.vregisters[x] as i64,
+                Register::I => chip8.i as i64,
+                Register::Pc => chip8.pc as i64,
+                Register::Sp => chip8.stackpointer as i64,
+                Register::Dt => chip8.delay_timer as i64,
+                Register::St => chip8.sound_timer as i64,
+            },
+            // addresses outside of memory read as 0
+            Expr::Memory(addr) => usize::try_from(addr.eval(chip8)).ok().and_then(|addr| chip8.memory.get(addr)).copied().unwrap_or(0) as i64,
+            Expr::Not(expr) => (expr.eval(chip8) == 0) as i64,
+            Expr::Negate(expr) => expr.eval(chip8).wrapping_neg(),
+            Expr::Binary(Op::Or, a, b) => (a.eval(chip8) != 0 || b.eval(chip8) != 0) as i64,
+            Expr::Binary(Op::And, a, b) => (a.eval(chip8) != 0 && b.eval(chip8) != 0) as i64,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(chip8), b.eval(chip8));
+                match op {
+                    Op::Eq => (a == b) as i64,
+                    Op::Ne => (a != b) as i64,
+                    Op::Lt => (a < b) as i64,
+                    Op::Le => (a <= b) as i64,
+                    Op::Gt => (a > b) as i64,
+                    Op::Ge => (a >= b) as i64,
+                    Op::Add => a.wrapping_add(b),
+                    Op::Sub => a.wrapping_sub(b),
+                    Op::BitAnd => a & b,
+                    Op::Or | Op::And => unreachable!(),
+                }
+            },
+            Expr::In(value, start, end) => (start.eval(chip8)..end.eval(chip8)).contains(&value.eval(chip8)) as i64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// longer symbols first, so that "<=" isn't read as "<" and "="
+static SYMBOLS: [&str; 17] = ["..", "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "&", "!", "(", ")", "[", "]"];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let word = &rest[..end];
+            let number = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                Some(i64::from_str_radix(hex, 16))
+            }
+            else if let Some(binary) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+                Some(i64::from_str_radix(binary, 2))
+            }
+            else if c.is_ascii_digit() {
+                Some(word.parse::<i64>())
+            }
+            else {
+                None
+            };
+            tokens.push(match number {
+                Some(value) => Token::Number(value.map_err(|_| format!("invalid number '{}'", word))?),
+                None => Token::Name(word.to_string()),
+            });
+            rest = &rest[end..];
+        }
+        else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)).ok_or_else(|| format!("unexpected '{}'", c))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    at: usize,
+    symbols: &'a SymbolMap,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    // consumes the next token if it is the symbol
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(next)) if *next == symbol) {
+            self.at += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) { Ok(()) } else { Err(format!("expected '{}'", symbol)) }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let expr = self.sum()?;
+        if self.peek() == Some(&Token::Name("in".to_string())) {
+            self.at += 1;
+            let start = self.sum()?;
+            self.expect("..")?;
+            let end = self.sum()?;
+            return Ok(Expr::In(Box::new(expr), Box::new(start), Box::new(end)));
+        }
+        for (symbol, op) in [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)] {
+            if self.eat(symbol) {
+                return Ok(Expr::Binary(op, Box::new(expr), Box::new(self.sum()?)));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat("+") { Op::Add } else if self.eat("-") { Op::Sub } else if self.eat("&") { Op::BitAnd } else { break };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of the condition")?;
+        self.at += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+            Token::Name(name) if name == "mem" => {
+                self.expect("[")?;
+                let addr = self.or()?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(addr)))
+            },
+            Token::Name(name) => {
+                let register = match name.to_lowercase().as_str() {
+                    "i" => Some(Register::I),
+                    "pc" => Some(Register::Pc),
+                    "sp" => Some(Register::Sp),
+                    "dt" => Some(Register::Dt),
+                    "st" => Some(Register::St),
+                    lower => lower.strip_prefix('v')
+                        .filter(|x| x.len() == 1)
+                        .and_then(|x| usize::from_str_radix(x, 16).ok())
+                        .map(Register::V),
+                };
+                match register {
+                    Some(register) => Ok(Expr::Register(register)),
+                    None => self.symbols.labels.get(&name).map(|&addr| Expr::Number(addr as i64)).ok_or(format!("unknown name '{}'", name)),
+                }
+            },
+            Token::Symbol(symbol) => Err(format!("unexpected '{}'", symbol)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    // labels are looked up in symbols, and stand for the address they had when the condition was parsed
+    pub fn parse(text: &str, symbols: &SymbolMap) -> Result<Condition, String> {
+        let mut parser = Parser { tokens: tokenize(text)?, at: 0, symbols };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(match token {
+                Token::Number(value) => format!("unexpected {}", value),
+                Token::Name(name) => format!("unexpected '{}'", name),
+                Token::Symbol(symbol) => format!("unexpected '{}'", symbol),
+            });
+        }
+        Ok(Condition { text: text.trim().to_string(), expr })
+    }
+
+    pub fn value(&self, chip8: &Chip8) -> i64 {
+        self.expr.eval(chip8)
+    }
+
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        self.value(chip8) != 0
+    }
+}
+
+// the condition as it was written
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,121 @@
+// Marks the memory addresses a program executes, reads as data and writes, for measuring how much of a ROM a
+// test run exercised and finding code that modifies itself.
+//
+// Opcodes fetched by a cycle are executed, the bytes of the XO-CHIP long load's address included. Sprites drawn
+// with DXYN and the bytes loaded by FX65, 5XY3 and F002 are read as data. The bytes FX33, FX55 and 5XY2 store are
+// written.
+//
+// The map has a line per address of the ROM and per address outside of it that was used, with a summary on top:
+//   <address> <flags>
+// The flags are 'x' for executed, 'r' for read and 'w' for written, or '-' in their place when not.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use crate::chip8::Chip8;
+use crate::instruction::Instruction;
+
+pub const EXECUTED: u8 = 1;
+pub const READ: u8 = 2;
+pub const WRITTEN: u8 = 4;
+
+// where ROMs are loaded
+const ROM_START: usize = 0x200;
+
+#[derive(Default)]
+pub struct Coverage {
+    // where the map is written
+    path: Option<PathBuf>,
+    rom_len: usize,
+    // the flags of every address, grown with memory
+    flags: Vec<u8>,
+}
+
+// consecutive addresses joined into ranges, e.g. "0x2A0-0x2A3, 0x300"
+fn ranges(addrs: &[usize]) -> String {
+    let mut out: Vec<(usize, usize)> = Vec::new();
+    for &addr in addrs {
+        match out.last_mut() {
+            Some((_, end)) if *end + 1 == addr => *end = addr,
+            _ => out.push((addr, addr)),
+        }
+    }
+    out.iter()
+        .map(|&(start, end)| if start == end { format!("0x{:03X}", start) } else { format!("0x{:03X}-0x{:03X}", start, end) })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Coverage {
+    // rom_len is the length of the ROM loaded at 0x200, whose addresses are all listed and counted in the summary
+    pub fn new(rom_len: usize) -> Self {
+        Coverage { rom_len, ..Coverage::default() }
+    }
+
+    // the map is created now so that a path that can't be written is found before running
+    pub fn create(path: &str, rom_len: usize) -> io::Result<Self> {
+        File::create(path)?;
+        Ok(Coverage { path: Some(PathBuf::from(path)), ..Coverage::new(rom_len) })
+    }
+
+    // the EXECUTED, READ and WRITTEN flags of an address
+    pub fn flags(&self, addr: usize) -> u8 {
+        self.flags.get(addr).copied().unwrap_or(0)
+    }
+
+    fn mark(&mut self, start: usize, len: usize, flag: u8) {
+        let end = (start + len).min(self.flags.len());
+        for flags in &mut self.flags[start.min(end)..end] {
+            *flags |= flag;
+        }
+    }
+
+    // called after every executed instruction, with the value I had before it
+    pub fn record(&mut self, chip8: &Chip8, pc: u16, instruction: Instruction, i: u16) {
+        if self.flags.len() < chip8.memory.len() {
+            self.flags.resize(chip8.memory.len(), 0);
+        }
+        self.mark(pc as usize, instruction.size() as usize, EXECUTED);
+        if !chip8.draw_waiting(pc, instruction) {
+            self.mark(i as usize, instruction.bytes_read(chip8.mode, chip8.planes), READ);
+        }
+        self.mark(i as usize, instruction.bytes_written(), WRITTEN);
+    }
+
+    pub fn map(&self) -> String {
+        let rom = ROM_START..ROM_START + self.rom_len;
+        let count = |flag: u8| rom.clone().filter(|&addr| self.flags(addr) & flag != 0).count();
+        let used = rom.clone().filter(|&addr| self.flags(addr) != 0).count();
+        let percent = if self.rom_len == 0 { 0.0 } else { used as f64 * 100.0 / self.rom_len as f64 };
+
+        let mut out = String::new();
+        if !rom.is_empty() {
+            let _ = writeln!(out, "# ROM 0x{:03X}-0x{:03X}: {} of {} bytes used ({:.1}%), {} executed, {} read, {} written",
+                rom.start, rom.end - 1, used, self.rom_len, percent, count(EXECUTED), count(READ), count(WRITTEN));
+        }
+        let modified: Vec<usize> = (0..self.flags.len()).filter(|&addr| self.flags(addr) & (EXECUTED | WRITTEN) == EXECUTED | WRITTEN).collect();
+        if !modified.is_empty() {
+            let _ = writeln!(out, "# executed and written, self-modifying: {}", ranges(&modified));
+        }
+        out.push_str("# address flags: x executed, r read, w written\n");
+        for addr in 0..self.flags.len().max(rom.end) {
+            let flags = self.flags(addr);
+            if flags == 0 && !rom.contains(&addr) {
+                continue;
+            }
+            let flag = |bit: u8, c: char| if flags & bit != 0 { c } else { '-' };
+            let _ = writeln!(out, "{:04X} {}{}{}", addr, flag(EXECUTED, 'x'), flag(READ, 'r'), flag(WRITTEN, 'w'));
+        }
+        out
+    }
+
+    // writes the map, if the coverage has a path
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, self.map())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,510 @@
+// Debug Adapter Protocol server, so that editors can launch and debug ROMs.
+// Messages are read from and written to a pair of streams, normally stdin and stdout.
+// The cpu runs on a thread of its own at 60 frames per second, through the same Debugger as the terminal prompt.
+//
+// Launch arguments:
+//   program       path of the ROM
+//   symbols       path of a symbol map, defaults to the ROM path with a '.sym' extension if that exists
+//   mode          "chip8", "schip" or "xochip"
+//   quirks        quirks preset, defaults to the mode's preset
+//   ipf           instructions per frame, 8 by default
+//   seed          seed for the random number generator
+//   stopOnEntry   pause before the first instruction
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::chip8::{Chip8, Mode};
+use crate::debugger::Debugger;
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::quirks::Quirks;
+use crate::symbols::SymbolMap;
+
+const THREAD_ID: i64 = 1;
+
+// variablesReference values of the scopes
+const REGISTERS: i64 = 1;
+const TIMERS: i64 = 2;
+const STACK: i64 = 3;
+
+// writes messages with increasing sequence numbers, shared with the emulation thread
+struct Output {
+    writer: Box<dyn Write + Send>,
+    seq: i64,
+}
+
+impl Output {
+    fn send(&mut self, message: Vec<(&str, Json)>) {
+        self.seq += 1;
+        let mut members = vec![("seq".to_string(), Json::from(self.seq))];
+        members.extend(message.into_iter().map(|(key, value)| (key.to_string(), value)));
+        let body = Json::Object(members).to_string();
+        let _ = write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = self.writer.flush();
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(vec![("type", "event".into()), ("event", event.into()), ("body", body)]);
+    }
+}
+
+struct Session {
+    chip8: Arc<RwLock<Chip8>>,
+    debugger: Arc<Mutex<Debugger>>,
+    output: Arc<Mutex<Output>>,
+    symbols: Option<SymbolMap>,
+    // directory the symbol map was loaded from, its source path is relative to it
+    symbols_dir: PathBuf,
+    // breakpoints set by source line and by address, the debugger gets the union of both
+    source_breakpoints: BTreeMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    cycles_per_frame: u32,
+    // the emulation thread is started by the first launch, a session debugs a single ROM
+    launched: bool,
+}
+
+// serves a single debug session until the client disconnects
+pub fn serve<R: BufRead>(mut reader: R, writer: Box<dyn Write + Send>) -> io::Result<()> {
+    let mut session = Session {
+        chip8: Arc::new(RwLock::new(Chip8::new())),
+        debugger: Arc::new(Mutex::new(Debugger::new(8))),
+        output: Arc::new(Mutex::new(Output { writer, seq: 0 })),
+        symbols: None,
+        symbols_dir: PathBuf::new(),
+        source_breakpoints: BTreeMap::new(),
+        instruction_breakpoints: Vec::new(),
+        stop_on_entry: false,
+        cycles_per_frame: 8,
+        launched: false,
+    };
+
+    while let Some(message) = read_message(&mut reader)? {
+        if message.get("type").as_str() != Some("request") {
+            continue;
+        }
+        let command = message.get("command").as_str().unwrap_or("").to_string();
+        let request_seq = message.get("seq").as_i64().unwrap_or(0);
+        let result = session.handle(&command, message.get("arguments"));
+
+        let mut response = vec![
+            ("type", Json::from("response")),
+            ("request_seq", request_seq.into()),
+            ("command", command.as_str().into()),
+        ];
+        match result {
+            Ok(body) => {
+                response.push(("success", true.into()));
+                response.push(("body", body));
+            },
+            Err(message) => {
+                response.push(("success", false.into()));
+                response.push(("message", message.into()));
+            },
+        }
+        session.output.lock().unwrap().send(response);
+        session.after(&command);
+
+        if command == "disconnect" || command == "terminate" {
+            let _chip8 = session.chip8.write().unwrap();
+            session.debugger.lock().unwrap().quit = true;
+            break;
+        }
+    }
+    Ok(())
+}
+
+// reads a message with its Content-Length header, or None at the end of the stream
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    // the body is read as it arrives, a bogus length can't allocate more than the stream holds
+    let length = length.unwrap();
+    let mut body = Vec::new();
+    reader.by_ref().take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the stream ended in the middle of a message"));
+    }
+    let text = String::from_utf8_lossy(&body);
+    Json::parse(&text).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+impl Session {
+    fn handle(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsReadMemoryRequest", true.into()),
+                ("supportsInstructionBreakpoints", true.into()),
+                ("supportsTerminateRequest", true.into()),
+            ])),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => Ok(self.set_instruction_breakpoints(args)),
+            "configurationDone" => Ok(Json::Null),
+            "threads" => Ok(Json::object([
+                ("threads", vec![Json::object([("id", THREAD_ID.into()), ("name", "CHIP-8".into())])].into()),
+            ])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(Json::object([("scopes", vec![
+                Json::object([("name", "Registers".into()), ("variablesReference", REGISTERS.into()), ("expensive", false.into())]),
+                Json::object([("name", "Timers".into()), ("variablesReference", TIMERS.into()), ("expensive", false.into())]),
+                Json::object([("name", "Stack".into()), ("variablesReference", STACK.into()), ("expensive", false.into())]),
+            ].into())])),
+            "variables" => Ok(self.variables(args.get("variablesReference").as_i64().unwrap_or(0))),
+            "readMemory" => self.read_memory(args),
+            "continue" => Ok(Json::object([("allThreadsContinued", true.into())])),
+            "next" | "stepIn" | "stepOut" => Ok(Json::Null),
+            "pause" => {
+                let _chip8 = self.chip8.write().unwrap();
+                self.debugger.lock().unwrap().paused = true;
+                Ok(Json::Null)
+            },
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("Unsupported request '{}'", command)),
+        }
+    }
+
+    // running and events that have to follow the response to a request
+    fn after(&mut self, command: &str) {
+        match command {
+            "continue" => {
+                let _chip8 = self.chip8.write().unwrap();
+                self.debugger.lock().unwrap().resume();
+            },
+            "next" | "stepIn" | "stepOut" => self.step(command),
+            "initialize" => self.output.lock().unwrap().event("initialized", Json::object([])),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", None);
+                }
+                else {
+                    let _chip8 = self.chip8.write().unwrap();
+                    self.debugger.lock().unwrap().resume();
+                }
+            },
+            "pause" => self.stopped("pause", None),
+            _ => {},
+        }
+    }
+
+    fn stopped(&self, reason: &str, description: Option<String>) {
+        send_stopped(&self.output, reason, description);
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        if self.launched {
+            return Err("A ROM is already launched in this session".to_string());
+        }
+        let program = args.get("program").as_str().ok_or("Missing 'program' in the launch arguments")?;
+        let rom = std::fs::read(program).map_err(|err| format!("Unable to read {}: {}", program, err))?;
+
+        let mode = match args.get("mode").as_str() {
+            Some(name) => Mode::from_name(name).ok_or(format!("Unknown mode '{}'", name))?,
+            None => Mode::Chip8,
+        };
+        let quirks = match args.get("quirks").as_str() {
+            Some(name) => Quirks::from_preset(name).ok_or(format!("Unknown quirks preset '{}'", name))?,
+            None => mode.default_quirks(),
+        };
+        self.cycles_per_frame = args.get("ipf").as_i64().unwrap_or(8).max(1) as u32;
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+
+        // the symbol map is optional, breakpoints by line need it
+        let symbols_path = match args.get("symbols").as_str() {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(Path::new(program).with_extension("sym")).filter(|path| path.exists()),
+        };
+        if let Some(path) = symbols_path {
+            self.symbols = Some(SymbolMap::load(&path)?);
+            self.symbols_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        }
+
+        {
+            let mut chip8 = self.chip8.write().unwrap();
+            chip8.set_mode(mode);
+            chip8.quirks = quirks;
+            if let Some(seed) = args.get("seed").as_i64() {
+                chip8.seed_rng(seed as u64);
+            }
+            chip8.load_program(&rom).map_err(|err| err.to_string())?;
+            *self.debugger.lock().unwrap() = Debugger::new(self.cycles_per_frame);
+        }
+        self.update_breakpoints();
+        spawn_emulation(self.chip8.clone(), self.debugger.clone(), self.output.clone());
+        self.launched = true;
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let path = args.get("source").get("path").as_str().unwrap_or("").to_string();
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array().unwrap_or(&[]) {
+            let line = breakpoint.get("line").as_i64().unwrap_or(0) as u32;
+            match self.address_of_line(&path, line) {
+                Ok((line, addr)) => {
+                    addresses.push(addr);
+                    breakpoints.push(Json::object([
+                        ("verified", true.into()),
+                        ("line", (line as i64).into()),
+                        ("instructionReference", format!("0x{:04X}", addr).into()),
+                    ]));
+                },
+                Err(message) => {
+                    breakpoints.push(Json::object([("verified", false.into()), ("line", (line as i64).into()), ("message", message.into())]));
+                },
+            }
+        }
+        self.source_breakpoints.insert(path, addresses);
+        self.update_breakpoints();
+        Ok(Json::object([("breakpoints", breakpoints.into())]))
+    }
+
+    // references with an offset that doesn't land on a 16-bit address aren't verified
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Json {
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array().unwrap_or(&[]) {
+            let addr = breakpoint.get("instructionReference").as_str().and_then(parse_reference)
+                .and_then(|addr| addr.checked_add(breakpoint.get("offset").as_i64().unwrap_or(0)))
+                .and_then(|addr| u16::try_from(addr).ok());
+            match addr {
+                Some(addr) => {
+                    self.instruction_breakpoints.push(addr);
+                    breakpoints.push(Json::object([("verified", true.into()), ("instructionReference", format!("0x{:04X}", addr).into())]));
+                },
+                None => breakpoints.push(Json::object([("verified", false.into()), ("message", "Invalid instruction reference".into())])),
+            }
+        }
+        self.update_breakpoints();
+        Json::object([("breakpoints", breakpoints.into())])
+    }
+
+    fn address_of_line(&self, path: &str, line: u32) -> Result<(u32, u16), String> {
+        let symbols = self.symbols.as_ref().ok_or("No symbol map is loaded")?;
+        if let Some(source) = &symbols.source {
+            if Path::new(source).file_name() != Path::new(path).file_name() {
+                return Err(format!("The symbol map is for {}", source));
+            }
+        }
+        symbols.address_of_line(line).ok_or_else(|| "No code at or after this line".to_string())
+    }
+
+    fn update_breakpoints(&self) {
+        let _chip8 = self.chip8.write().unwrap();
+        let mut debugger = self.debugger.lock().unwrap();
+        debugger.breakpoints = self.source_breakpoints.values().flatten().chain(&self.instruction_breakpoints).copied().collect();
+    }
+
+    // next steps over subroutine calls, stepOut runs until the current subroutine returns
+    fn step(&mut self, command: &str) {
+        let chip8arc = self.chip8.clone();
+        let mut chip8 = chip8arc.write().unwrap();
+        let mut debugger = self.debugger.lock().unwrap();
+        let opcode = chip8.memory.get(chip8.pc as usize..chip8.pc as usize + 2).map(|x| (x[0] as u16) << 8 | x[1] as u16);
+        let is_call = matches!(opcode.map(Instruction::decode), Some(Ok(Instruction::Call(_))));
+
+        if command == "stepOut" && chip8.stackpointer > 0 {
+            let depth = chip8.stackpointer - 1;
+            debugger.temporary = Some((chip8.jumpstack[depth as usize], depth));
+            debugger.resume();
+        }
+        else if command == "next" && is_call {
+            debugger.temporary = Some((chip8.pc.wrapping_add(2), chip8.stackpointer));
+            debugger.resume();
+        }
+        else {
+            match debugger.step(&mut chip8) {
+                Ok(()) => send_stopped(&self.output, "step", None),
+                Err(fault) => send_stopped(&self.output, "exception", Some(fault.to_string())),
+            }
+        }
+    }
+
+    fn stack_trace(&self) -> Json {
+        let chip8 = self.chip8.read().unwrap();
+
+        // the current pc, then the 2NNN instruction of every subroutine call on the stack
+        let mut addresses = vec![chip8.pc];
+        addresses.extend(chip8.jumpstack[..chip8.stackpointer as usize].iter().rev().map(|addr| addr.wrapping_sub(2)));
+
+        let frames: Vec<Json> = addresses.iter().enumerate().map(|(id, &addr)| {
+            let name = self.symbols.as_ref().map(|symbols| symbols.describe(addr)).unwrap_or_else(|| format!("0x{:03X}", addr));
+            let location = self.symbols.as_ref().and_then(|symbols| Some((symbols.source.as_ref()?, symbols.line_of_address(addr)?)));
+            let mut frame = vec![
+                ("id".to_string(), Json::from(id as i64)),
+                ("name".to_string(), name.into()),
+                ("line".to_string(), location.map_or(0, |(_, line)| line as i64).into()),
+                ("column".to_string(), Json::from(0)),
+                ("instructionPointerReference".to_string(), format!("0x{:04X}", addr).into()),
+            ];
+            if let Some((source, _)) = location {
+                frame.push(("source".to_string(), Json::object([
+                    ("name", source.as_str().into()),
+                    ("path", self.symbols_dir.join(source).to_string_lossy().into_owned().into()),
+                ])));
+            }
+            Json::Object(frame)
+        }).collect();
+
+        let total = frames.len() as i64;
+        Json::object([("stackFrames", frames.into()), ("totalFrames", total.into())])
+    }
+
+    fn variables(&self, reference: i64) -> Json {
+        let chip8 = self.chip8.read().unwrap();
+        let variable = |name: String, value: String, memory: Option<u16>| {
+            let mut members = vec![
+                ("name".to_string(), Json::from(name)),
+                ("value".to_string(), value.into()),
+                ("variablesReference".to_string(), 0.into()),
+            ];
+            if let Some(addr) = memory {
+                members.push(("memoryReference".to_string(), format!("0x{:04X}", addr).into()));
+            }
+            Json::Object(members)
+        };
+
+        let variables: Vec<Json> = match reference {
+            REGISTERS => {
+                let mut registers: Vec<Json> = chip8.vregisters.iter().enumerate()
+                    .map(|(index, value)| variable(format!("V{:X}", index), format!("0x{:02X}", value), None))
+                    .collect();
+                registers.push(variable("I".to_string(), format!("0x{:03X}", chip8.i), Some(chip8.i)));
+                registers.push(variable("PC".to_string(), format!("0x{:03X}", chip8.pc), Some(chip8.pc)));
+                registers.push(variable("SP".to_string(), chip8.stackpointer.to_string(), None));
+                registers
+            },
+            TIMERS => vec![
+                variable("DT".to_string(), chip8.delay_timer.to_string(), None),
+                variable("ST".to_string(), chip8.sound_timer.to_string(), None),
+            ],
+            STACK => chip8.jumpstack[..chip8.stackpointer as usize].iter().enumerate()
+                .map(|(index, &addr)| variable(format!("[{}]", index), format!("0x{:03X}", addr), Some(addr)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Json::object([("variables", variables.into())])
+    }
+
+    fn read_memory(&self, args: &Json) -> Result<Json, String> {
+        let chip8 = self.chip8.read().unwrap();
+        let reference = args.get("memoryReference").as_str().and_then(parse_reference).ok_or("Invalid memory reference")?;
+        let start = reference.checked_add(args.get("offset").as_i64().unwrap_or(0)).ok_or("Invalid memory offset")?;
+        let count = args.get("count").as_i64().unwrap_or(0).max(0);
+        let end = start.checked_add(count).ok_or("Invalid memory count")?;
+
+        // bytes outside of memory are unreadable
+        let first = start.clamp(0, chip8.memory.len() as i64);
+        let last = end.clamp(0, chip8.memory.len() as i64);
+        let data = &chip8.memory[first as usize..last as usize];
+        Ok(Json::object([
+            ("address", format!("0x{:04X}", first).into()),
+            ("data", base64(data).into()),
+            ("unreadableBytes", (count - data.len() as i64).into()),
+        ]))
+    }
+}
+
+fn send_stopped(output: &Mutex<Output>, reason: &str, description: Option<String>) {
+    let mut body = vec![
+        ("reason".to_string(), Json::from(reason)),
+        ("threadId".to_string(), THREAD_ID.into()),
+        ("allThreadsStopped".to_string(), true.into()),
+    ];
+    if let Some(description) = description {
+        body.push(("text".to_string(), description.into()));
+    }
+    output.lock().unwrap().event("stopped", Json::Object(body));
+}
+
+// runs 60 frames a second while the debugger isn't paused, and tells the client when it stops
+fn spawn_emulation(chip8arc: Arc<RwLock<Chip8>>, debuggerarc: Arc<Mutex<Debugger>>, output: Arc<Mutex<Output>>) {
+    std::thread::spawn(move || {
+        let frame_time = Duration::from_nanos(1_000_000_000 / 60);
+        let mut next_frame_time = Instant::now();
+        let mut exited = false;
+        loop {
+            {
+                let mut chip8 = chip8arc.write().unwrap();
+                let mut debugger = debuggerarc.lock().unwrap();
+                if debugger.quit {
+                    return;
+                }
+
+                let was_paused = debugger.paused;
+                let temporary = debugger.temporary;
+                let result = debugger.run_frame(&mut chip8);
+                if !was_paused {
+                    match result {
+                        Err(fault) => send_stopped(&output, "exception", Some(fault.to_string())),
+                        Ok(true) if temporary.is_some() && temporary == Some((chip8.pc, chip8.stackpointer)) => send_stopped(&output, "step", None),
+                        Ok(true) => send_stopped(&output, "breakpoint", None),
+                        Ok(false) => {},
+                    }
+                }
+                if chip8.halted && !exited {
+                    exited = true;
+                    let mut output = output.lock().unwrap();
+                    output.event("exited", Json::object([("exitCode", 0.into())]));
+                    output.event("terminated", Json::object([]));
+                }
+            }
+
+            next_frame_time += frame_time;
+            let now = Instant::now();
+            if next_frame_time > now {
+                std::thread::sleep(next_frame_time - now);
+            }
+            else {
+                next_frame_time = now;
+            }
+        }
+    });
+}
+
+// addresses are sent as hex strings
+fn parse_reference(text: &str) -> Option<i64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse::<i64>().ok(),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    static ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - index * 6) & 0x3F) as usize] as char);
+            }
+            else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,527 @@
+// Interactive debugger that runs the cpu in place of Chip8::run_frame, stopping at breakpoints and watchpoints,
+// and a small command language to step, inspect and edit the live Chip8 from a terminal.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+use crate::chip8::Chip8;
+use crate::condition::Condition;
+use crate::fault::CpuFault;
+use crate::instruction::Instruction;
+
+static HELP: &str = "\
+Commands (numbers are decimal, or hex with a 0x prefix, addresses can also be labels like main+0x4):
+  s, step [n]             run n instructions, 1 by default
+  c, continue             run until a breakpoint is hit
+  p, pause                stop running
+  b, break [addr] [if c]  set a breakpoint at addr that stops when condition c holds, or list the breakpoints
+  d, delete <addr|all>    clear the breakpoint at addr, or all of them
+  w, watch [spec]         pause after an instruction, or list the watchpoints, spec being one of:
+                            if <condition>                 once the condition becomes true
+                            [read|write|access] <addr>[..end]  when memory in the range is read or written
+                            v<x>                           when the register changes
+  unwatch <n|all>         clear watchpoint n, or all of them
+  r, regs                 print the registers, stack and timers
+  m, mem <addr> [len]     print len bytes of memory starting at addr, 64 by default
+  l, list [addr] [n]      disassemble n instructions around addr, the pc by default
+  set <reg> <value>       set v0-vf, i, pc, dt or st
+  poke <addr> <bytes..>   write bytes to memory starting at addr
+  h, help                 print this help
+  q, quit                 exit";
+
+pub static PROMPT: &str = "(chip-8) ";
+
+// what a watchpoint pauses on, checked after every instruction while running
+pub enum Watch {
+    // the condition becoming true, held being whether it held after the last instruction
+    Condition { condition: Condition, held: bool },
+    // DXYN, FX65, 5XY3 and F002 reading or FX33, FX55 and 5XY2 writing memory in start..end
+    Memory { start: u16, end: u16, read: bool, write: bool },
+    // the register changing from value
+    Register { x: usize, value: u8 },
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Condition { condition, .. } => write!(f, "if {}", condition),
+            Watch::Memory { start, end, read, write } => {
+                let access = match (read, write) {
+                    (true, false) => "read",
+                    (false, true) => "write",
+                    _ => "access",
+                };
+                write!(f, "{} 0x{:03X}..0x{:03X}", access, start, end)
+            },
+            Watch::Register { x, .. } => write!(f, "v{:x}", x),
+        }
+    }
+}
+
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    // conditions of breakpoints, which only stop when theirs holds
+    pub conditions: BTreeMap<u16, Condition>,
+    pub watches: Vec<Watch>,
+    // what the watchpoint that last paused execution saw, cleared when it resumes
+    pub hit: Option<String>,
+    // a one-off breakpoint at an address and stack depth, used to step over and out of subroutines
+    pub temporary: Option<(u16, u16)>,
+    // the cpu isn't run while paused, commands are waited for instead
+    pub paused: bool,
+    // set by quit, the frontend should exit
+    pub quit: bool,
+    // the fault that last paused execution, cleared when it resumes
+    pub fault: Option<CpuFault>,
+    cycles_per_frame: u32,
+    // instructions already run in the current frame, frames can be paused halfway
+    frame_cycles: u32,
+    // lets execution move off a breakpoint it has just stopped at
+    resuming: bool,
+}
+
+impl Debugger {
+    pub fn new(cycles_per_frame: u32) -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            conditions: BTreeMap::new(),
+            watches: Vec::new(),
+            hit: None,
+            temporary: None,
+            paused: true,
+            quit: false,
+            fault: None,
+            cycles_per_frame,
+            frame_cycles: 0,
+            resuming: false,
+        }
+    }
+
+    // runs a single instruction, ticking the timers whenever a whole frame's worth has run
+    fn cycle(&mut self, chip8: &mut Chip8) -> Result<(), CpuFault> {
+        self.resuming = false;
+        let result = chip8.single_cycle();
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.cycles_per_frame {
+            chip8.tick_timers();
+            self.frame_cycles = 0;
+        }
+        result
+    }
+
+    // runs a single instruction while paused, breakpoints don't apply but watchpoints still see it
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<(), CpuFault> {
+        self.paused = true;
+        self.fault = None;
+        self.hit = None;
+        if chip8.halted {
+            return Ok(());
+        }
+        let (pc, i) = (chip8.pc, chip8.i);
+        let instruction = if self.watches.is_empty() { None } else { decode_at(chip8, pc) };
+        let result = self.cycle(chip8);
+        self.fault = result.err();
+        if result.is_ok() && !self.watches.is_empty() {
+            self.hit = self.check_watches(chip8, pc, instruction, i);
+        }
+        result
+    }
+
+    // continues running from the pc, even if there is a breakpoint on it
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+        self.fault = None;
+        self.hit = None;
+    }
+
+    // what to say about where execution stopped, the watchpoint that paused it or the pc
+    pub fn stop_message(&self, chip8: &Chip8) -> String {
+        match &self.hit {
+            Some(hit) => hit.clone(),
+            None => format!("Breakpoint at {}", chip8.symbols.describe(chip8.pc)),
+        }
+    }
+
+    // adds a watchpoint from a spec as the watch command takes it, e.g. "if v3 > 10", "write 0x300..0x320" or "v3"
+    pub fn watch(&mut self, chip8: &Chip8, spec: &str) -> Result<String, String> {
+        let spec = spec.trim();
+        let watch = if let Some(condition) = spec.strip_prefix("if ") {
+            let condition = Condition::parse(condition, &chip8.symbols)?;
+            // only a change to true pauses, so a condition that holds already waits for it to stop holding first
+            let held = condition.holds(chip8);
+            Watch::Condition { condition, held }
+        }
+        else if let Some(x) = register_index(spec) {
+            Watch::Register { x, value: chip8.vregisters[x] }
+        }
+        else {
+            let (access, range) = match spec.split_once(' ') {
+                Some((access, range)) => (access, range.trim()),
+                None => ("access", spec),
+            };
+            let (read, write) = match access {
+                "read" => (true, false),
+                "write" => (false, true),
+                "access" => (true, true),
+                _ => return Err(format!("Unknown watchpoint '{}'", spec)),
+            };
+            let (start, end) = match range.split_once("..") {
+                Some((start, end)) => (parse_address(chip8, start)?, parse_address(chip8, end)?),
+                None => {
+                    let addr = parse_address(chip8, range)?;
+                    (addr, addr.saturating_add(1))
+                },
+            };
+            if start >= end {
+                return Err(format!("Empty range '{}'", range));
+            }
+            Watch::Memory { start, end, read, write }
+        };
+        self.watches.push(watch);
+        Ok(format!("Watchpoint {}: {}", self.watches.len(), self.watches.last().unwrap()))
+    }
+
+    // takes the state as it is now for the watchpoints to compare against, after it was changed by hand
+    pub fn sync_watches(&mut self, chip8: &Chip8) {
+        for watch in &mut self.watches {
+            match watch {
+                Watch::Condition { condition, held } => *held = condition.holds(chip8),
+                Watch::Register { x, value } => *value = chip8.vregisters[*x],
+                Watch::Memory { .. } => {},
+            }
+        }
+    }
+
+    // updates the watchpoints after the instruction at pc ran with I at i, returning what the ones that trigger saw
+    fn check_watches(&mut self, chip8: &Chip8, pc: u16, instruction: Option<Instruction>, i: u16) -> Option<String> {
+        let at = chip8.symbols.describe(pc);
+        let mut hits = Vec::new();
+        for (index, watch) in self.watches.iter_mut().enumerate() {
+            let hit = match watch {
+                Watch::Condition { condition, held } => {
+                    let was = std::mem::replace(held, condition.holds(chip8));
+                    if *held && !was { Some(format!("became true after {}", at)) } else { None }
+                },
+                Watch::Memory { start, end, read, write } => {
+                    let instruction = match instruction {
+                        Some(instruction) => instruction,
+                        None => continue,
+                    };
+                    let reads = if *read && !chip8.draw_waiting(pc, instruction) { instruction.bytes_read(chip8.mode, chip8.planes) } else { 0 };
+                    let writes = if *write { instruction.bytes_written() } else { 0 };
+                    let overlaps = |len: usize| len > 0 && (i as usize) < *end as usize && i as usize + len > *start as usize;
+                    match (overlaps(reads), overlaps(writes)) {
+                        (true, _) => Some(format!("{} at {} read 0x{:03X}..0x{:03X}", instruction.pattern(), at, i, i as usize + reads)),
+                        (_, true) => Some(format!("{} at {} wrote 0x{:03X}..0x{:03X}", instruction.pattern(), at, i, i as usize + writes)),
+                        _ => None,
+                    }
+                },
+                Watch::Register { x, value } => {
+                    let was = std::mem::replace(value, chip8.vregisters[*x]);
+                    if *value != was { Some(format!("{:02X} -> {:02X} after {}", was, value, at)) } else { None }
+                },
+            };
+            if let Some(hit) = hit {
+                hits.push(format!("Watchpoint {} ({}): {}", index + 1, watch, hit));
+            }
+        }
+        if hits.is_empty() { None } else { Some(hits.join("\n")) }
+    }
+
+    // runs what is left of the current frame, stopping early at breakpoints and faults
+    // returns whether execution is paused, which a breakpoint can do halfway through the frame
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<bool, CpuFault> {
+        self.run_cycles(chip8, self.cycles_per_frame)
+    }
+
+    // like run_frame, but ends the frame early once it has run the given number of cycles, without ticking the
+    // timers unless that is a whole frame
+    pub fn run_cycles(&mut self, chip8: &mut Chip8, cycles: u32) -> Result<bool, CpuFault> {
+        if self.paused {
+            return Ok(true);
+        }
+        loop {
+            if chip8.halted {
+                // time keeps moving for a halted cpu
+                chip8.tick_timers();
+                self.frame_cycles = 0;
+                return Ok(false);
+            }
+            let condition = self.conditions.get(&chip8.pc);
+            if !self.resuming && self.breakpoints.contains(&chip8.pc) && condition.is_none_or(|condition| condition.holds(chip8)) {
+                self.paused = true;
+                self.temporary = None;
+                return Ok(true);
+            }
+            if self.temporary == Some((chip8.pc, chip8.stackpointer)) {
+                self.paused = true;
+                self.temporary = None;
+                return Ok(true);
+            }
+            let last_in_frame = self.frame_cycles + 1 >= cycles.min(self.cycles_per_frame);
+            // the instruction and I are taken before it runs, for the memory it accesses
+            let (pc, i) = (chip8.pc, chip8.i);
+            let instruction = if self.watches.is_empty() { None } else { decode_at(chip8, pc) };
+            if let Err(fault) = self.cycle(chip8) {
+                self.paused = true;
+                self.temporary = None;
+                self.fault = Some(fault);
+                return Err(fault);
+            }
+            if !self.watches.is_empty() {
+                if let Some(hit) = self.check_watches(chip8, pc, instruction, i) {
+                    self.paused = true;
+                    self.temporary = None;
+                    self.hit = Some(hit);
+                    return Ok(true);
+                }
+            }
+            if last_in_frame {
+                return Ok(false);
+            }
+        }
+    }
+
+    // executes a command line, returning the text to show for it
+    pub fn command(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return String::new();
+        }
+        match self.run_command(chip8, args[0], &args[1..]) {
+            Ok(output) => output,
+            Err(err) => format!("{}, type 'help' for a list of commands", err),
+        }
+    }
+
+    fn run_command(&mut self, chip8: &mut Chip8, name: &str, args: &[&str]) -> Result<String, String> {
+        match name {
+            "s" | "step" => {
+                let count = args.first().map(|x| parse_number(x)).transpose()?.unwrap_or(1);
+                self.paused = true;
+                for _ in 0..count {
+                    if let Err(fault) = self.step(chip8) {
+                        return Ok(format!("CPU fault: {}\n{}", fault.describe(&chip8.symbols), listing(chip8, chip8.pc, 0, 3, &self.breakpoints)));
+                    }
+                    // watchpoints end a run of steps like they pause a continue
+                    if let Some(hit) = &self.hit {
+                        return Ok(format!("{}\n{}", hit, listing(chip8, chip8.pc, 0, 3, &self.breakpoints)));
+                    }
+                }
+                Ok(listing(chip8, chip8.pc, 0, 3, &self.breakpoints))
+            },
+            "c" | "continue" => {
+                self.resume();
+                Ok("Continuing.".to_string())
+            },
+            "p" | "pause" => {
+                self.paused = true;
+                Ok(listing(chip8, chip8.pc, 2, 3, &self.breakpoints))
+            },
+            "b" | "break" => {
+                match args.first() {
+                    Some(addr) => {
+                        let addr = parse_address(chip8, addr)?;
+                        match args.get(1) {
+                            Some(&"if") => {
+                                let condition = Condition::parse(&args[2..].join(" "), &chip8.symbols)?;
+                                self.conditions.insert(addr, condition);
+                            },
+                            Some(_) => return Err("Usage: break <addr> [if <condition>]".to_string()),
+                            None => {
+                                self.conditions.remove(&addr);
+                            },
+                        }
+                        self.breakpoints.insert(addr);
+                        Ok(format!("Breakpoint set at {}", breakpoint(chip8, addr, &self.conditions)))
+                    },
+                    None if self.breakpoints.is_empty() => Ok("No breakpoints.".to_string()),
+                    None => Ok(self.breakpoints.iter().map(|&addr| breakpoint(chip8, addr, &self.conditions)).collect::<Vec<_>>().join("\n")),
+                }
+            },
+            "d" | "delete" => {
+                match args.first() {
+                    Some(&"all") => {
+                        self.breakpoints.clear();
+                        self.conditions.clear();
+                        Ok("Deleted all breakpoints.".to_string())
+                    },
+                    Some(addr) => {
+                        let addr = parse_address(chip8, addr)?;
+                        self.conditions.remove(&addr);
+                        if self.breakpoints.remove(&addr) {
+                            Ok(format!("Deleted breakpoint at {}", chip8.symbols.describe(addr)))
+                        }
+                        else {
+                            Err(format!("No breakpoint at {}", chip8.symbols.describe(addr)))
+                        }
+                    },
+                    None => Err("Missing address".to_string()),
+                }
+            },
+            "w" | "watch" => {
+                match args {
+                    [] if self.watches.is_empty() => Ok("No watchpoints.".to_string()),
+                    [] => Ok(self.watches.iter().enumerate().map(|(index, watch)| format!("{}: {}", index + 1, watch)).collect::<Vec<_>>().join("\n")),
+                    _ => self.watch(chip8, &args.join(" ")),
+                }
+            },
+            "unwatch" => {
+                match args.first() {
+                    Some(&"all") => {
+                        self.watches.clear();
+                        Ok("Deleted all watchpoints.".to_string())
+                    },
+                    Some(number) => {
+                        let index = parse_number(number)? as usize;
+                        if index == 0 || index > self.watches.len() {
+                            return Err(format!("No watchpoint {}", index));
+                        }
+                        let watch = self.watches.remove(index - 1);
+                        Ok(format!("Deleted watchpoint {}: {}", index, watch))
+                    },
+                    None => Err("Missing watchpoint number".to_string()),
+                }
+            },
+            "r" | "regs" => Ok(registers(chip8)),
+            "m" | "mem" => {
+                let addr = parse_address(chip8, args.first().ok_or("Missing address")?)? as usize;
+                let len = args.get(1).map(|x| parse_number(x)).transpose()?.unwrap_or(64) as usize;
+                Ok(hexdump(chip8, addr, len))
+            },
+            "l" | "list" => {
+                let addr = args.first().map(|x| parse_address(chip8, x)).transpose()?.unwrap_or(chip8.pc);
+                let count = args.get(1).map(|x| parse_number(x)).transpose()?.unwrap_or(11) as u16;
+                Ok(listing(chip8, addr, count / 2, count - count / 2, &self.breakpoints))
+            },
+            "set" => {
+                let (register, value) = match args {
+                    [register, value] => (register.to_lowercase(), parse_number(value)?),
+                    _ => return Err("Usage: set <reg> <value>".to_string()),
+                };
+                match register.as_str() {
+                    "i" => chip8.i = value as u16,
+                    "pc" => chip8.pc = value as u16,
+                    "dt" => chip8.delay_timer = value as u8,
+                    "st" => chip8.sound_timer = value as u8,
+                    _ => {
+                        let index = register_index(&register).ok_or(format!("Unknown register '{}'", register))?;
+                        chip8.vregisters[index] = value as u8;
+                    }
+                }
+                self.sync_watches(chip8);
+                Ok(registers(chip8))
+            },
+            "poke" => {
+                let addr = parse_address(chip8, args.first().ok_or("Missing address")?)? as usize;
+                if args.len() < 2 {
+                    return Err("Missing bytes".to_string());
+                }
+                for (offset, byte) in args[1..].iter().enumerate() {
+                    let byte = parse_number(byte)?;
+                    *chip8.memory.get_mut(addr + offset).ok_or(format!("0x{:X} is out of memory", addr + offset))? = byte as u8;
+                }
+                self.sync_watches(chip8);
+                Ok(hexdump(chip8, addr, args.len() - 1))
+            },
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" => {
+                self.quit = true;
+                Ok(String::new())
+            },
+            _ => Err(format!("Unknown command '{}'", name)),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse::<u32>(),
+    };
+    result.map_err(|_| format!("Invalid number '{}'", text))
+}
+
+// the index of a V register named like v3 or VA
+fn register_index(name: &str) -> Option<usize> {
+    name.strip_prefix(['v', 'V'])
+        .filter(|x| x.len() == 1)
+        .and_then(|x| usize::from_str_radix(x, 16).ok())
+}
+
+// the instruction at addr, None if it doesn't decode
+fn decode_at(chip8: &Chip8, addr: u16) -> Option<Instruction> {
+    let bytes = chip8.memory.get(addr as usize..addr as usize + 2)?;
+    Instruction::decode((bytes[0] as u16) << 8 | bytes[1] as u16).ok()
+}
+
+// a breakpoint's address and its condition
+fn breakpoint(chip8: &Chip8, addr: u16, conditions: &BTreeMap<u16, Condition>) -> String {
+    match conditions.get(&addr) {
+        Some(condition) => format!("{} if {}", chip8.symbols.describe(addr), condition),
+        None => chip8.symbols.describe(addr),
+    }
+}
+
+// a number or a label of the loaded ROM with an optional offset
+fn parse_address(chip8: &Chip8, text: &str) -> Result<u16, String> {
+    if let Some(addr) = chip8.symbols.resolve(text) {
+        return Ok(addr);
+    }
+    let addr = parse_number(text).map_err(|_| format!("Unknown address '{}'", text))?;
+    u16::try_from(addr).map_err(|_| format!("Address '{}' is too large", text))
+}
+
+// the registers, stack and timers
+pub fn registers(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    for (index, value) in chip8.vregisters.iter().enumerate() {
+        let _ = write!(out, "V{:X}={:02X}{}", index, value, if index % 8 == 7 { "\n" } else { " " });
+    }
+    let _ = writeln!(out, "I={:03X} PC={:03X} SP={} DT={} ST={}", chip8.i, chip8.pc, chip8.stackpointer, chip8.delay_timer, chip8.sound_timer);
+    let stack: Vec<String> = chip8.jumpstack[..chip8.stackpointer as usize].iter().map(|addr| format!("{:03X}", addr)).collect();
+    let _ = write!(out, "Stack: [{}]", stack.join(" "));
+    out
+}
+
+// len bytes of memory starting at addr, 16 per line
+pub fn hexdump(chip8: &Chip8, addr: usize, len: usize) -> String {
+    let end = (addr + len).min(chip8.memory.len());
+    let mut lines = Vec::new();
+    for start in (addr..end).step_by(16) {
+        let bytes: Vec<String> = chip8.memory[start..(start + 16).min(end)].iter().map(|byte| format!("{:02X}", byte)).collect();
+        lines.push(format!("0x{:03X}: {}", start, bytes.join(" ")));
+    }
+    lines.join("\n")
+}
+
+// disassembly of the instructions before and after addr, marking the pc with '>' and breakpoints with '*'
+// labels of the loaded ROM are shown on a line of their own before the instruction they are at
+pub fn listing(chip8: &Chip8, addr: u16, before: u16, after: u16, breakpoints: &BTreeSet<u16>) -> String {
+    let start = addr.saturating_sub(before * 2);
+    let mut lines = Vec::new();
+    for index in 0..before + after {
+        let at = start as usize + index as usize * 2;
+        if at + 1 >= chip8.memory.len() {
+            break;
+        }
+        let opcode = (chip8.memory[at] as u16) << 8 | chip8.memory[at + 1] as u16;
+        let text = match Instruction::decode(opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW 0x{:04X}", opcode),
+        };
+        for (name, _) in chip8.symbols.labels.iter().filter(|&(_, &label)| label as usize == at) {
+            lines.push(format!("{}:", name));
+        }
+        let marker = if at == chip8.pc as usize { '>' } else { ' ' };
+        let breakpoint = if breakpoints.contains(&(at as u16)) { '*' } else { ' ' };
+        lines.push(format!("{}{} 0x{:03X}: {:04X}  {}", marker, breakpoint, at, opcode, text));
+    }
+    lines.join("\n")
+}
+
+// what to show when execution stops
+pub fn stop_report(chip8: &Chip8, debugger: &Debugger) -> String {
+    format!("{}\n{}", registers(chip8), listing(chip8, chip8.pc, 2, 3, &debugger.breakpoints))
+}
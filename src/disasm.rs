@@ -0,0 +1,193 @@
+// Disassembles ROMs into a listing, separating code from data by following control flow from the entry point.
+//
+// Every path is followed from 0x200 through jumps, calls and both outcomes of skips, so the bytes that are never
+// reached as instructions are sprites or other data. Jump tables used with BNNN are followed for as long as they
+// hold jumps, since the offset in V0 isn't known without running the ROM.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::chip8::Mode;
+use crate::instruction::Instruction;
+
+const START: u16 = 0x200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Sub,
+    Label,
+    Data,
+}
+
+impl LabelKind {
+    fn name(&self, addr: u16) -> String {
+        match self {
+            LabelKind::Sub => format!("sub_{:03X}", addr),
+            LabelKind::Label => format!("label_{:03X}", addr),
+            LabelKind::Data => format!("data_{:03X}", addr),
+        }
+    }
+}
+
+struct Disassembly<'a> {
+    rom: &'a [u8],
+    mode: Mode,
+    // addresses that start an instruction, and the decoded instruction
+    code: BTreeMap<u16, Instruction>,
+    // the label at an address, subroutines taking precedence over jump targets and data
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Disassembly<'_> {
+    fn contains(&self, addr: u16) -> bool {
+        addr >= START && ((addr - START) as usize) < self.rom.len()
+    }
+
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(START)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn instruction(&self, addr: u16) -> Option<Instruction> {
+        let instruction = Instruction::decode(self.word(addr)?).ok()?;
+        if !instruction.supported_by(self.mode) || !addr.checked_add(instruction.size() - 1).is_some_and(|last| self.contains(last)) {
+            return None;
+        }
+        Some(instruction)
+    }
+
+    fn label(&mut self, addr: u16, kind: LabelKind) {
+        if self.contains(addr) {
+            let label = self.labels.entry(addr).or_insert(kind);
+            *label = (*label).min(kind);
+        }
+    }
+
+    // follows every path from the entry point, recording the instructions and labels it finds
+    fn trace(&mut self) {
+        let mut pending = vec![START];
+        while let Some(addr) = pending.pop() {
+            if self.code.contains_key(&addr) {
+                continue;
+            }
+            // decode errors and instructions from other instruction sets end the path, they are most likely data
+            let instruction = match self.instruction(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            self.code.insert(addr, instruction);
+            // wraps to 0 past the end of memory, which ends the path
+            let next = addr.wrapping_add(instruction.size());
+
+            match instruction {
+                Instruction::Jump(nnn) => {
+                    self.label(nnn, LabelKind::Label);
+                    pending.push(nnn);
+                },
+                Instruction::Call(nnn) => {
+                    self.label(nnn, LabelKind::Sub);
+                    pending.push(nnn);
+                    pending.push(next);
+                },
+                Instruction::JumpOffset(nnn) => {
+                    self.label(nnn, LabelKind::Label);
+                    let mut entry = nnn;
+                    while let Some(Instruction::Jump(_)) = self.instruction(entry) {
+                        pending.push(entry);
+                        entry = entry.wrapping_add(2);
+                    }
+                },
+                // machine code routines can't be followed, and are usually zeroed data reached by mistake
+                Instruction::Return | Instruction::Exit | Instruction::Sys(_) => {},
+                _ if instruction.is_skip() => {
+                    pending.push(next);
+                    // the skipped instruction is 4 bytes long if it is the XO-CHIP long load
+                    let skipped = if self.mode == Mode::XoChip && self.word(next) == Some(0xF000) { 4 } else { 2 };
+                    pending.push(next.wrapping_add(skipped));
+                },
+                Instruction::LoadI(nnn) => {
+                    self.label(nnn, LabelKind::Data);
+                    pending.push(next);
+                },
+                Instruction::LoadLong => {
+                    if let Some(nnnn) = self.word(addr + 2) {
+                        self.label(nnnn, LabelKind::Data);
+                    }
+                    pending.push(next);
+                },
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    // the instruction's assembly, with addresses replaced by the labels at them
+    fn render(&self, addr: u16, instruction: Instruction) -> String {
+        let target = match instruction {
+            Instruction::Jump(nnn) | Instruction::Call(nnn) | Instruction::LoadI(nnn) | Instruction::JumpOffset(nnn) => nnn,
+            Instruction::LoadLong => {
+                let nnnn = self.word(addr + 2).unwrap_or(0);
+                return match self.labels.get(&nnnn) {
+                    Some(kind) => format!("LD I, LONG {}", kind.name(nnnn)),
+                    None => format!("LD I, LONG 0x{:04X}", nnnn),
+                };
+            },
+            _ => return instruction.to_string(),
+        };
+        match self.labels.get(&target) {
+            Some(kind) => instruction.to_string().replace(&format!("0x{:03X}", target), &kind.name(target)),
+            None => instruction.to_string(),
+        }
+    }
+
+    fn listing(&self) -> String {
+        let mut out = String::new();
+        let end = START as usize + self.rom.len();
+        let mut addr = START;
+        while (addr as usize) < end {
+            if let Some(kind) = self.labels.get(&addr) {
+                let _ = writeln!(out, "{}:", kind.name(addr));
+            }
+            match self.code.get(&addr) {
+                Some(&instruction) => {
+                    let bytes: String = (0..instruction.size()).map(|offset| format!("{:02X}", self.rom[(addr + offset - START) as usize])).collect();
+                    let _ = writeln!(out, "  0x{:03X}: {:<8}  {}", addr, bytes, self.render(addr, instruction));
+                    // labels can point into the middle of an instruction, at its operand or at overlapping code
+                    for offset in 1..instruction.size() {
+                        let inside = addr.wrapping_add(offset);
+                        if let Some(kind) = self.labels.get(&inside) {
+                            let byte = ["second", "third", "fourth"][offset as usize - 1];
+                            let _ = match self.code.get(&inside) {
+                                Some(&overlapping) => writeln!(out, "{}:  (the {} byte of 0x{:03X}, also runs as {})", kind.name(inside), byte, addr, self.render(inside, overlapping)),
+                                None => writeln!(out, "{}:  (the {} byte of 0x{:03X})", kind.name(inside), byte, addr),
+                            };
+                        }
+                    }
+                    addr = match addr.checked_add(instruction.size()) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                },
+                None => {
+                    // data is shown a byte per line, with its bits drawn as a sprite row
+                    let byte = self.rom[(addr - START) as usize];
+                    let sprite: String = (0..8).rev().map(|bit| if byte >> bit & 1 == 1 { '#' } else { '.' }).collect();
+                    let _ = writeln!(out, "  0x{:03X}: {:<8}  DB 0x{:02X}  {}", addr, format!("{:02X}", byte), byte, sprite);
+                    addr = match addr.checked_add(1) {
+                        Some(next) => next,
+                        None => break,
+                    };
+                },
+            }
+        }
+        out
+    }
+}
+
+// a listing of a ROM loaded at 0x200, decoding the instructions that the given mode supports
+pub fn disassemble(rom: &[u8], mode: Mode) -> String {
+    let rom = &rom[..rom.len().min(mode.memory_size() - START as usize)];
+    let mut disassembly = Disassembly { rom, mode, code: BTreeMap::new(), labels: BTreeMap::new() };
+    disassembly.trace();
+    disassembly.listing()
+}
@@ -0,0 +1,35 @@
+use crate::chip8::Chip8;
+
+// characters used for each combination of planes, plain CHIP-8 only ever uses the first two
+static ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+// the display as text, one line per row
+pub fn ascii(chip8: &Chip8) -> String {
+    let width = chip8.display_width();
+    let height = chip8.display_height();
+
+    let mut out = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        for x in 0..width {
+            out.push(ASCII_PIXELS[(chip8.display[x * height + y] & 0x3) as usize]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// the display as a plain (P1) PBM image, pixels set in any plane are black
+pub fn pbm(chip8: &Chip8) -> String {
+    let width = chip8.display_width();
+    let height = chip8.display_height();
+
+    let mut out = format!("P1\n{} {}\n", width, height);
+    for y in 0..height {
+        let row: Vec<&str> = (0..width)
+            .map(|x| if chip8.display[x * height + y] != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&row.join(" "));
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use crate::symbols::SymbolMap;
+
+// errors raised by the cpu while running a rom, pc is the address of the faulting instruction
+// an instruction that faults part way through keeps what it did before the fault, e.g. DXYN leaves the rows drawn
+// before the sprite ran past the end of memory and FX33 or FX55 the bytes stored before it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuFault {
+    // 2NNN called with all 16 stack levels in use
+    StackOverflow { pc: u16 },
+    // 00EE with nothing on the stack to return to
+    StackUnderflow { pc: u16 },
+    // an instruction read or wrote past the end of memory
+    MemoryOutOfBounds { pc: u16, addr: usize },
+    // the program counter ran past the end of memory
+    PcOutOfBounds { pc: u16 },
+    // the opcode isn't an instruction in the current mode
+    UnknownOpcode { pc: u16, opcode: u16 },
+}
+
+impl CpuFault {
+    // the message with the pc shown relative to a label, e.g. "stack overflow at main+0x4"
+    pub fn describe(&self, symbols: &SymbolMap) -> String {
+        match *self {
+            CpuFault::StackOverflow { pc } => format!("stack overflow at {}", symbols.describe(pc)),
+            CpuFault::StackUnderflow { pc } => format!("stack underflow at {}", symbols.describe(pc)),
+            CpuFault::MemoryOutOfBounds { pc, addr } => format!("memory access out of bounds at {} (address 0x{:X})", symbols.describe(pc), addr),
+            CpuFault::PcOutOfBounds { pc } => format!("program counter out of bounds (0x{:X})", pc),
+            CpuFault::UnknownOpcode { pc, opcode } => format!("unknown opcode {:04X} at {}", opcode, symbols.describe(pc)),
+        }
+    }
+}
+
+// without labels, addresses are shown in hex
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolMap::default()))
+    }
+}
+
+impl std::error::Error for CpuFault {}
+
+// what the frontend does when the cpu faults
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultPolicy {
+    // print the fault and stop executing
+    Halt,
+    // print the fault and carry on with the next instruction, halting if the next frame ends in the same fault
+    Log,
+    // silently carry on with the next instruction, halting if the next frame ends in the same fault
+    Ignore,
+}
+
+pub static POLICY_NAMES: [&str; 3] = ["halt", "log", "ignore"];
+
+impl FaultPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "halt" => Some(FaultPolicy::Halt),
+            "log" => Some(FaultPolicy::Log),
+            "ignore" => Some(FaultPolicy::Ignore),
+            _ => None
+        }
+    }
+}
@@ -1,40 +1,41 @@
-use std::fs::{File, metadata};
-use std::io::Read;
-use std::path::PathBuf;
+use std::io;
+use std::path::Path;
 
 use savefile::{save_file, load_file};
 
-use crate::chip8::Chip8;
+use crate::chip8::{Chip8, STATE_VERSION};
 
-pub fn get_file_as_byte_vec(filename: &str) -> Vec<u8> {
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = metadata(&filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-
-    buffer
-}
-
-pub fn save_state(filename: &PathBuf, chip8inst: &Chip8) {
-    save_file(filename, 0, chip8inst).unwrap_or_else(|x| {
-        println!("{}", x);
-    });
-    println!("State saved: {}", filename.to_str().unwrap());
+pub fn get_file_as_byte_vec(filename: &str) -> io::Result<Vec<u8>> {
+    std::fs::read(filename)
 }
 
-pub fn load_state(filename: &PathBuf, chip8inst: &mut Chip8) {
-    if filename.exists() {
-        match load_file::<Chip8, PathBuf>(filename.to_path_buf(), 0) {
-            Ok(state) => {
-                *chip8inst = state;
-            },
-            Err(x) => {
-                println!("{}", x);
-            }
-        }
-        println!("State loaded: {}", filename.to_str().unwrap());
+// loads a rom into memory, or resumes from a save state if the path is a '.state' file
+// returns the length of the rom, 0 for a state
+pub fn load_rom_or_state(path: &str, chip8inst: &mut Chip8) -> io::Result<usize> {
+    if path.ends_with(".state") {
+        load_state(Path::new(path), chip8inst)?;
+        Ok(0)
     }
     else {
-        println!("No state file found!");
+        let program = get_file_as_byte_vec(path)?;
+        chip8inst.load_program(&program)?;
+        Ok(program.len())
     }
-}
\ No newline at end of file
+}
+
+pub fn save_state(filename: &Path, chip8inst: &Chip8) -> io::Result<()> {
+    save_file(filename, STATE_VERSION, chip8inst).map_err(|err| io::Error::other(err.to_string()))?;
+    println!("State saved at {}: {}", chip8inst.symbols.describe(chip8inst.pc), filename.display());
+    Ok(())
+}
+
+// the machine is left as it was if the state can't be loaded
+pub fn load_state(filename: &Path, chip8inst: &mut Chip8) -> io::Result<()> {
+    let state = load_file::<Chip8, &Path>(filename, STATE_VERSION).map_err(|err| match err {
+        savefile::SavefileError::IOError { io_error } => io_error,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    })?;
+    chip8inst.restore_from(state)?;
+    println!("State loaded at {}: {}", chip8inst.symbols.describe(chip8inst.pc), filename.display());
+    Ok(())
+}
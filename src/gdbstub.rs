@@ -0,0 +1,385 @@
+// GDB remote serial protocol server, so that gdb and other debugger frontends can attach over TCP.
+// Execution is controlled through a shared Debugger, the frontend keeps running frames with it
+// while the stub only pauses, resumes and single-steps.
+//
+// Registers are numbered V0-VF (0-15, 8 bits), I (16), PC (17), SP (18), DT (19) and ST (20).
+// I and PC are 16 bits and sent big-endian, like everything else in CHIP-8 memory.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::chip8::Chip8;
+use crate::debugger::Debugger;
+use crate::fault::CpuFault;
+
+const REGISTER_COUNT: usize = 21;
+
+static TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// waits for a single gdb connection on a local port and serves it until it detaches
+pub fn listen(port: u16, chip8: Arc<RwLock<Chip8>>, debugger: Arc<Mutex<Debugger>>) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(stream, chip8, debugger).serve()
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    chip8: Arc<RwLock<Chip8>>,
+    debugger: Arc<Mutex<Debugger>>,
+    // set by QStartNoAckMode, packets are no longer acknowledged with '+'
+    no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new(stream: TcpStream, chip8: Arc<RwLock<Chip8>>, debugger: Arc<Mutex<Debugger>>) -> Self {
+        GdbStub { stream, chip8, debugger, no_ack: false }
+    }
+
+    // handles packets until the client detaches, kills the target or disconnects
+    pub fn serve(&mut self) -> io::Result<()> {
+        // packets are tiny and each one waits for a reply, so don't let them be held back
+        self.stream.set_nodelay(true)?;
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+            match packet.as_bytes().first() {
+                Some(b'c') => {
+                    let reply = self.resume()?;
+                    self.send(&reply)?;
+                },
+                Some(b'D') => {
+                    // leave the rom running as if the debugger was never attached
+                    self.send("OK")?;
+                    let _chip8 = self.chip8.write().unwrap();
+                    self.debugger.lock().unwrap().resume();
+                    return Ok(());
+                },
+                Some(b'k') => {
+                    let _chip8 = self.chip8.write().unwrap();
+                    self.debugger.lock().unwrap().quit = true;
+                    return Ok(());
+                },
+                _ => {
+                    let reply = self.handle(&packet);
+                    self.send(&reply)?;
+                },
+            }
+        }
+    }
+
+    // replies to every packet that doesn't let the cpu run freely
+    fn handle(&mut self, packet: &str) -> String {
+        if packet.starts_with('q') || packet.starts_with('Q') {
+            return self.query(packet);
+        }
+
+        // an empty packet, or one starting with a multibyte character, isn't a command this stub knows
+        let (command, args) = match packet.split_at_checked(1) {
+            Some((command, args)) if !command.is_empty() => (command, args),
+            _ => return String::new(),
+        };
+        let mut chip8 = self.chip8.write().unwrap();
+        let mut debugger = self.debugger.lock().unwrap();
+        match command {
+            "?" => stop_reply(&chip8, &debugger),
+            "g" => {
+                (0..REGISTER_COUNT).map(|index| read_register(&chip8, index)).collect()
+            },
+            "G" => {
+                let mut rest = args;
+                let mut written = true;
+                for index in 0..REGISTER_COUNT {
+                    let width = register_width(index) * 2;
+                    match rest.get(..width) {
+                        Some(value) if write_register(&mut chip8, index, value) => rest = &rest[width..],
+                        _ => {
+                            written = false;
+                            break;
+                        },
+                    }
+                }
+                // the registers before a bad one are written all the same
+                debugger.sync_watches(&chip8);
+                if written { "OK".to_string() } else { "E01".to_string() }
+            },
+            "p" => {
+                match usize::from_str_radix(args, 16) {
+                    Ok(index) if index < REGISTER_COUNT => read_register(&chip8, index),
+                    _ => "E01".to_string(),
+                }
+            },
+            "P" => {
+                let written = args.split_once('=').and_then(|(index, value)| {
+                    let index = usize::from_str_radix(index, 16).ok().filter(|&index| index < REGISTER_COUNT)?;
+                    write_register(&mut chip8, index, value).then_some(())
+                });
+                debugger.sync_watches(&chip8);
+                if written.is_some() { "OK".to_string() } else { "E01".to_string() }
+            },
+            "m" => {
+                let range = args.split_once(',').and_then(|(addr, len)| {
+                    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+                });
+                match range {
+                    Some((addr, len)) if addr < chip8.memory.len() => {
+                        let end = addr.saturating_add(len).min(chip8.memory.len());
+                        chip8.memory[addr..end].iter().map(|byte| format!("{:02x}", byte)).collect()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = range.split_once(',')?;
+                    let addr = usize::from_str_radix(addr, 16).ok()?;
+                    let bytes = decode_hex(data)?;
+                    let end = addr.checked_add(bytes.len()).filter(|&end| end <= chip8.memory.len())?;
+                    (usize::from_str_radix(len, 16).ok()? == bytes.len()).then_some((addr..end, bytes))
+                });
+                match write {
+                    Some((range, bytes)) => {
+                        chip8.memory[range].copy_from_slice(&bytes);
+                        debugger.sync_watches(&chip8);
+                        "OK".to_string()
+                    },
+                    None => "E01".to_string(),
+                }
+            },
+            "Z" | "z" => {
+                // only software breakpoints, Z0,addr,kind
+                let mut parts = args.split(',');
+                if parts.next() != Some("0") {
+                    return String::new();
+                }
+                match parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok()) {
+                    Some(addr) => {
+                        if command == "Z" {
+                            debugger.breakpoints.insert(addr);
+                        }
+                        else {
+                            debugger.breakpoints.remove(&addr);
+                        }
+                        "OK".to_string()
+                    },
+                    None => "E01".to_string(),
+                }
+            },
+            "s" => {
+                let _ = debugger.step(&mut chip8);
+                stop_reply(&chip8, &debugger)
+            },
+            "H" => "OK".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // general queries and settings, q and Q packets
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;QStartNoAckMode+;qXfer:features:read+".to_string();
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            return "OK".to_string();
+        }
+        if packet == "qAttached" {
+            return "1".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            // reply with the requested window of the target description
+            let (offset, len) = match range.split_once(',') {
+                Some(window) => window,
+                None => return "E01".to_string(),
+            };
+            let offset = usize::from_str_radix(offset, 16).unwrap_or(usize::MAX);
+            let len = usize::from_str_radix(len, 16).unwrap_or(0);
+            if offset >= TARGET_XML.len() {
+                return "l".to_string();
+            }
+            let end = offset.saturating_add(len).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return format!("{}{}", marker, &TARGET_XML[offset..end]);
+        }
+        String::new()
+    }
+
+    // lets the frontend run the cpu until it stops at a breakpoint or fault, or the client interrupts it
+    fn resume(&mut self) -> io::Result<String> {
+        {
+            let _chip8 = self.chip8.write().unwrap();
+            self.debugger.lock().unwrap().resume();
+        }
+
+        self.stream.set_read_timeout(Some(Duration::from_millis(10)))?;
+        let result = self.wait_for_stop();
+        self.stream.set_read_timeout(None)?;
+        result
+    }
+
+    fn wait_for_stop(&mut self) -> io::Result<String> {
+        let mut byte = [0];
+        loop {
+            {
+                let chip8 = self.chip8.read().unwrap();
+                let debugger = self.debugger.lock().unwrap();
+                if chip8.halted {
+                    return Ok("W00".to_string());
+                }
+                if debugger.paused {
+                    return Ok(stop_reply(&chip8, &debugger));
+                }
+            }
+
+            // ctrl-c from the client is sent as a single 0x03 byte
+            match self.stream.read(&mut byte) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) if byte[0] == 0x03 => {
+                    let _chip8 = self.chip8.write().unwrap();
+                    self.debugger.lock().unwrap().paused = true;
+                    return Ok("S02".to_string());
+                },
+                Ok(_) => {},
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {},
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // reads the next packet, acknowledging it, or None when the connection is closed
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // skip acks and interrupts until the start of a packet
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|x| u8::from_str_radix(x, 16).ok());
+            let valid = expected == Some(checksum_of(&data));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+// why execution stopped, as a signal number: a trap for breakpoints and steps, otherwise the fault
+fn stop_reply(chip8: &Chip8, debugger: &Debugger) -> String {
+    if chip8.halted {
+        return "W00".to_string();
+    }
+    let signal = match debugger.fault {
+        None => 5,
+        Some(CpuFault::UnknownOpcode { .. }) => 4,
+        Some(_) => 11,
+    };
+    format!("S{:02x}", signal)
+}
+
+// size of a register in bytes
+fn register_width(index: usize) -> usize {
+    if index == 16 || index == 17 { 2 } else { 1 }
+}
+
+fn read_register(chip8: &Chip8, index: usize) -> String {
+    match index {
+        0..=15 => format!("{:02x}", chip8.vregisters[index]),
+        16 => format!("{:04x}", chip8.i),
+        17 => format!("{:04x}", chip8.pc),
+        18 => format!("{:02x}", chip8.stackpointer),
+        19 => format!("{:02x}", chip8.delay_timer),
+        _ => format!("{:02x}", chip8.sound_timer),
+    }
+}
+
+// sets a register from its hex value, returning false if the value isn't valid
+fn write_register(chip8: &mut Chip8, index: usize, value: &str) -> bool {
+    if value.len() != register_width(index) * 2 {
+        return false;
+    }
+    let value = match u16::from_str_radix(value, 16) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+    match index {
+        0..=15 => chip8.vregisters[index] = value as u8,
+        16 => chip8.i = value,
+        17 => chip8.pc = value,
+        // the stack pointer can't go past the 16 levels of stack
+        18 if value as usize <= chip8.jumpstack.len() => chip8.stackpointer = value,
+        18 => return false,
+        19 => chip8.delay_timer = value as u8,
+        _ => chip8.sound_timer = value as u8,
+    }
+    true
+}
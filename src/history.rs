@@ -0,0 +1,173 @@
+// Undo information recorded for every executed instruction, for stepping the machine backwards.
+//
+// Each entry keeps the registers as they were before the instruction, the memory bytes it overwrote and the
+// display pixels it changed. Timer ticks between instructions are undone along with the instruction after them,
+// as its registers were saved after the tick. Keys aren't restored, and neither is a custom rng's state.
+
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+use crate::fault::CpuFault;
+use crate::instruction::Instruction;
+use crate::rng::SplitMix64;
+
+// everything an instruction can change other than memory and the display
+struct Registers {
+    opcode: u16,
+    vregisters: [u8; 16],
+    i: u16,
+    pc: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    jumpstack: [u16; 16],
+    stackpointer: u16,
+    waiting_key: Option<u8>,
+    planes: u8,
+    hires: bool,
+    halted: bool,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    rpl_flags: [u8; 16],
+    vblank_interrupt: bool,
+    rng: SplitMix64,
+}
+
+impl Registers {
+    fn save(chip8: &Chip8) -> Self {
+        Registers {
+            opcode: chip8.opcode,
+            vregisters: chip8.vregisters,
+            i: chip8.i,
+            pc: chip8.pc,
+            delay_timer: chip8.delay_timer,
+            sound_timer: chip8.sound_timer,
+            jumpstack: chip8.jumpstack,
+            stackpointer: chip8.stackpointer,
+            waiting_key: chip8.waiting_key,
+            planes: chip8.planes,
+            hires: chip8.hires,
+            halted: chip8.halted,
+            audio_pattern: chip8.audio_pattern,
+            pitch: chip8.pitch,
+            rpl_flags: chip8.rpl_flags,
+            vblank_interrupt: chip8.vblank_interrupt,
+            rng: chip8.rng.clone(),
+        }
+    }
+
+    fn restore(self, chip8: &mut Chip8) {
+        chip8.opcode = self.opcode;
+        chip8.vregisters = self.vregisters;
+        chip8.i = self.i;
+        chip8.pc = self.pc;
+        chip8.delay_timer = self.delay_timer;
+        chip8.sound_timer = self.sound_timer;
+        chip8.jumpstack = self.jumpstack;
+        chip8.stackpointer = self.stackpointer;
+        chip8.waiting_key = self.waiting_key;
+        chip8.planes = self.planes;
+        chip8.hires = self.hires;
+        chip8.halted = self.halted;
+        chip8.audio_pattern = self.audio_pattern;
+        chip8.pitch = self.pitch;
+        chip8.rpl_flags = self.rpl_flags;
+        chip8.vblank_interrupt = self.vblank_interrupt;
+        chip8.rng = self.rng;
+    }
+}
+
+enum DisplayUndo {
+    Unchanged,
+    // (index, old value) of the pixels that changed
+    Pixels(Vec<(u32, u8)>),
+    // the whole display, when switching resolutions resized it
+    Whole(Vec<u8>),
+}
+
+struct Undo {
+    registers: Registers,
+    // the address and old contents of the memory the instruction may have written
+    memory: Option<(usize, Vec<u8>)>,
+    display: DisplayUndo,
+}
+
+pub struct History {
+    // the most instructions that can be undone
+    budget: usize,
+    entries: VecDeque<Undo>,
+}
+
+impl History {
+    pub fn new(budget: usize) -> Self {
+        History { budget, entries: VecDeque::new() }
+    }
+
+    // the amount of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // runs one instruction with cycle, recording how to undo it, faulting instructions included
+    pub fn record(&mut self, chip8: &mut Chip8, cycle: impl FnOnce(&mut Chip8) -> Result<(), CpuFault>) -> Result<(), CpuFault> {
+        if self.budget == 0 {
+            return cycle(chip8);
+        }
+
+        // the instruction is decoded ahead of time to know what it will write
+        let pc = chip8.pc as usize;
+        let instruction = chip8.memory.get(pc..pc + 2).and_then(|bytes| Instruction::decode((bytes[0] as u16) << 8 | bytes[1] as u16).ok());
+        let registers = Registers::save(chip8);
+        let memory = instruction.map(|instruction| instruction.bytes_written()).filter(|&len| len > 0).map(|len| {
+            let start = (chip8.i as usize).min(chip8.memory.len());
+            let end = (start + len).min(chip8.memory.len());
+            (start, chip8.memory[start..end].to_vec())
+        });
+        let display = instruction.filter(Instruction::changes_display).map(|_| chip8.display.clone());
+
+        let result = cycle(chip8);
+
+        let display = match display {
+            None => DisplayUndo::Unchanged,
+            Some(old) if old.len() != chip8.display.len() => DisplayUndo::Whole(old),
+            Some(old) => DisplayUndo::Pixels(old.iter().zip(&chip8.display).enumerate()
+                .filter(|(_, (old, new))| old != new)
+                .map(|(index, (&old, _))| (index as u32, old))
+                .collect()),
+        };
+        self.entries.push_back(Undo { registers, memory, display });
+        if self.entries.len() > self.budget {
+            self.entries.pop_front();
+        }
+        result
+    }
+
+    // undoes the last recorded instruction, false when there is nothing left to undo
+    pub fn undo(&mut self, chip8: &mut Chip8) -> bool {
+        let undo = match self.entries.pop_back() {
+            Some(undo) => undo,
+            None => return false,
+        };
+        undo.registers.restore(chip8);
+        if let Some((start, bytes)) = undo.memory {
+            chip8.memory[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+        match undo.display {
+            DisplayUndo::Unchanged => {},
+            DisplayUndo::Pixels(pixels) => {
+                for (index, old) in pixels {
+                    chip8.display[index as usize] = old;
+                }
+            },
+            DisplayUndo::Whole(display) => chip8.display = display,
+        }
+        true
+    }
+}
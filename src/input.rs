@@ -1,6 +1,10 @@
 use glium::glutin::event::{KeyboardInput, ElementState, VirtualKeyCode};
 
-use crate::{chip8::Chip8, args::Flags, fstools::{self, load_state}};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chip_8_emu::{chip8::Chip8, fstools::{self, load_state}};
+
+use crate::args::Flags;
 
 static KEYMAP: [usize; 16] = [
     0x1, // 1
@@ -21,7 +25,7 @@ static KEYMAP: [usize; 16] = [
     0xF, // F
 ];
 
-pub fn parse_input(input: KeyboardInput, chip8inst: &mut Chip8, flags: &Flags) {
+pub fn parse_input(input: KeyboardInput, chip8inst: &mut Chip8, flags: &Flags, rewinding: &AtomicBool) {
     let pressed = (input.state == ElementState::Pressed) as u8;
     match input.virtual_keycode.unwrap_or(VirtualKeyCode::L) {
         VirtualKeyCode::Key1=> {
@@ -84,7 +88,9 @@ pub fn parse_input(input: KeyboardInput, chip8inst: &mut Chip8, flags: &Flags) {
                 let rompath = std::path::Path::new(flags.rom_path.as_str());
                 let statepath = rompath.with_extension("state");
 
-                fstools::save_state(&statepath, chip8inst);
+                if let Err(err) = fstools::save_state(&statepath, chip8inst) {
+                    println!("Unable to save {}: {}", statepath.display(), err);
+                }
             }
         },
         VirtualKeyCode::F6 => {
@@ -92,9 +98,16 @@ pub fn parse_input(input: KeyboardInput, chip8inst: &mut Chip8, flags: &Flags) {
                 let rompath = std::path::Path::new(flags.rom_path.as_str());
                 let statepath = rompath.with_extension("state");
 
-                load_state(&statepath, chip8inst)
+                if let Err(err) = load_state(&statepath, chip8inst) {
+                    println!("Unable to load {}: {}", statepath.display(), err);
+                }
             }
         },
+
+        // rewind while held
+        VirtualKeyCode::Back => {
+            rewinding.store(pressed == 1, Ordering::Relaxed);
+        },
         _ => {}
     }
 }
\ No newline at end of file
@@ -0,0 +1,492 @@
+use std::fmt;
+
+use crate::chip8::Mode;
+
+// X and Y are indices of the V registers, N/NN/NNN are immediate values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    // 00CN - scroll the display down by N pixels (SUPER-CHIP)
+    ScrollDown(u8),
+    // 00DN - scroll the display up by N pixels (XO-CHIP)
+    ScrollUp(u8),
+    // 00E0 - clear the display
+    Clear,
+    // 00EE - return from a subroutine
+    Return,
+    // 00FB - scroll the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    // 00FC - scroll the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    // 00FD - exit the interpreter (SUPER-CHIP)
+    Exit,
+    // 00FE - switch to lo-res mode (SUPER-CHIP)
+    Lores,
+    // 00FF - switch to hi-res mode (SUPER-CHIP)
+    Hires,
+    // 0NNN - call machine code routine at NNN, ignored by modern interpreters
+    Sys(u16),
+    // 1NNN - jump to address NNN
+    Jump(u16),
+    // 2NNN - call subroutine at NNN
+    Call(u16),
+    // 3XNN - skip next instruction if VX == NN
+    SkipEqImm { x: usize, nn: u8 },
+    // 4XNN - skip next instruction if VX != NN
+    SkipNeImm { x: usize, nn: u8 },
+    // 5XY0 - skip next instruction if VX == VY
+    SkipEqReg { x: usize, y: usize },
+    // 5XY2 - store VX to VY in memory starting at address I (XO-CHIP)
+    SaveRange { x: usize, y: usize },
+    // 5XY3 - read VX to VY from memory starting at address I (XO-CHIP)
+    LoadRange { x: usize, y: usize },
+    // 6XNN - set VX to NN
+    LoadImm { x: usize, nn: u8 },
+    // 7XNN - add NN to VX
+    AddImm { x: usize, nn: u8 },
+    // 8XY0 - set VX to VY
+    Move { x: usize, y: usize },
+    // 8XY1 - set VX to VX | VY
+    Or { x: usize, y: usize },
+    // 8XY2 - set VX to VX & VY
+    And { x: usize, y: usize },
+    // 8XY3 - set VX to VX ^ VY
+    Xor { x: usize, y: usize },
+    // 8XY4 - set VX to VX + VY, set VF to 1 if carry
+    Add { x: usize, y: usize },
+    // 8XY5 - set VX to VX - VY, set VF to 0 if borrow
+    Sub { x: usize, y: usize },
+    // 8XY6 - set VX to VX >> 1, set VF to LSB
+    ShiftRight { x: usize, y: usize },
+    // 8XY7 - set VX to VY - VX, set VF to 0 if borrow
+    SubReverse { x: usize, y: usize },
+    // 8XYE - set VX to VX << 1, set VF to MSB
+    ShiftLeft { x: usize, y: usize },
+    // 9XY0 - skip next instruction if VX != VY
+    SkipNeReg { x: usize, y: usize },
+    // ANNN - set I to NNN
+    LoadI(u16),
+    // BNNN - jump to address NNN + V0
+    JumpOffset(u16),
+    // CXNN - set VX to random byte ANDed with NN
+    Random { x: usize, nn: u8 },
+    // DXYN - draw sprite at VX, VY with N bytes of sprite data starting at I
+    Draw { x: usize, y: usize, n: u8 },
+    // EX9E - skip next instruction if key in VX is pressed
+    SkipKey(usize),
+    // EXA1 - skip next instruction if key in VX is not pressed
+    SkipNotKey(usize),
+    // F000 NNNN - set I to the 16 bit address stored after the opcode (XO-CHIP)
+    LoadLong,
+    // FN01 - select the planes N to draw on (XO-CHIP)
+    SelectPlanes(u8),
+    // F002 - load the 16 byte audio pattern starting at I (XO-CHIP)
+    LoadAudio,
+    // FX07 - set VX to delay timer value
+    GetDelay(usize),
+    // FX0A - wait for keypress, store in VX
+    WaitKey(usize),
+    // FX15 - set delay timer to VX
+    SetDelay(usize),
+    // FX18 - set sound timer to VX
+    SetSound(usize),
+    // FX1E - add VX to I
+    AddI(usize),
+    // FX29 - set I to location of sprite for digit VX
+    LoadFont(usize),
+    // FX30 - set I to location of the large sprite for digit VX (SUPER-CHIP)
+    LoadBigFont(usize),
+    // FX33 - store BCD representation of VX in memory locations I, I+1, and I+2
+    Bcd(usize),
+    // FX3A - set the audio pattern playback pitch to VX (XO-CHIP)
+    SetPitch(usize),
+    // FX55 - store V0 to VX in memory starting at address I
+    Store(usize),
+    // FX65 - read V0 to VX from memory starting at address I
+    Load(usize),
+    // FX75 - store V0 to VX in the RPL user flags (SUPER-CHIP)
+    SaveFlags(usize),
+    // FX85 - read V0 to VX from the RPL user flags (SUPER-CHIP)
+    LoadFlags(usize),
+}
+
+// returned for opcodes that no supported instruction set defines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:04X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    // the instruction set that introduced the instruction
+    pub fn mode(&self) -> Mode {
+        match self {
+            Instruction::ScrollDown(_) | Instruction::ScrollRight | Instruction::ScrollLeft | Instruction::Exit |
+            Instruction::Lores | Instruction::Hires | Instruction::LoadBigFont(_) | Instruction::SaveFlags(_) |
+            Instruction::LoadFlags(_) => Mode::SuperChip,
+            Instruction::ScrollUp(_) | Instruction::SaveRange { .. } | Instruction::LoadRange { .. } |
+            Instruction::LoadLong | Instruction::SelectPlanes(_) | Instruction::LoadAudio |
+            Instruction::SetPitch(_) => Mode::XoChip,
+            _ => Mode::Chip8,
+        }
+    }
+
+    // whether the instruction can be executed in the given mode, XO-CHIP being a superset of SUPER-CHIP
+    pub fn supported_by(&self, mode: Mode) -> bool {
+        match self.mode() {
+            Mode::Chip8 => true,
+            Mode::SuperChip => mode != Mode::Chip8,
+            Mode::XoChip => mode == Mode::XoChip,
+        }
+    }
+
+    // size in bytes, the XO-CHIP long load is followed by the word it loads into I
+    pub fn size(&self) -> u16 {
+        if *self == Instruction::LoadLong { 4 } else { 2 }
+    }
+
+    // whether the instruction conditionally skips the one after it
+    pub fn is_skip(&self) -> bool {
+        matches!(self,
+            Instruction::SkipEqImm { .. } | Instruction::SkipNeImm { .. } | Instruction::SkipEqReg { .. } |
+            Instruction::SkipNeReg { .. } | Instruction::SkipKey(_) | Instruction::SkipNotKey(_))
+    }
+
+    // the number of bytes the instruction writes to memory, starting at I
+    pub fn bytes_written(&self) -> usize {
+        match *self {
+            Instruction::Bcd(_) => 3,
+            Instruction::Store(x) => x + 1,
+            Instruction::SaveRange { x, y } => x.abs_diff(y) + 1,
+            _ => 0,
+        }
+    }
+
+    // the number of bytes the instruction reads from memory as data, starting at I
+    // sprites are read for each selected plane, the SUPER-CHIP 16x16 sprite being 32 bytes
+    pub fn bytes_read(&self, mode: Mode, planes: u8) -> usize {
+        match *self {
+            Instruction::Draw { n, .. } => {
+                let len = if n == 0 && mode != Mode::Chip8 { 32 } else { n as usize };
+                len * (planes & 0x3).count_ones() as usize
+            },
+            Instruction::Load(x) => x + 1,
+            Instruction::LoadRange { x, y } => x.abs_diff(y) + 1,
+            Instruction::LoadAudio => 16,
+            _ => 0,
+        }
+    }
+
+    // whether the instruction can change the display
+    pub fn changes_display(&self) -> bool {
+        matches!(self,
+            Instruction::Clear | Instruction::Draw { .. } | Instruction::ScrollDown(_) | Instruction::ScrollUp(_) |
+            Instruction::ScrollRight | Instruction::ScrollLeft | Instruction::Lores | Instruction::Hires)
+    }
+
+    // the opcode with its operands as letters, as the instructions are commonly listed, e.g. DXYN
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::ScrollDown(_) => "00CN",
+            Instruction::ScrollUp(_) => "00DN",
+            Instruction::Clear => "00E0",
+            Instruction::Return => "00EE",
+            Instruction::ScrollRight => "00FB",
+            Instruction::ScrollLeft => "00FC",
+            Instruction::Exit => "00FD",
+            Instruction::Lores => "00FE",
+            Instruction::Hires => "00FF",
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipEqImm { .. } => "3XNN",
+            Instruction::SkipNeImm { .. } => "4XNN",
+            Instruction::SkipEqReg { .. } => "5XY0",
+            Instruction::SaveRange { .. } => "5XY2",
+            Instruction::LoadRange { .. } => "5XY3",
+            Instruction::LoadImm { .. } => "6XNN",
+            Instruction::AddImm { .. } => "7XNN",
+            Instruction::Move { .. } => "8XY0",
+            Instruction::Or { .. } => "8XY1",
+            Instruction::And { .. } => "8XY2",
+            Instruction::Xor { .. } => "8XY3",
+            Instruction::Add { .. } => "8XY4",
+            Instruction::Sub { .. } => "8XY5",
+            Instruction::ShiftRight { .. } => "8XY6",
+            Instruction::SubReverse { .. } => "8XY7",
+            Instruction::ShiftLeft { .. } => "8XYE",
+            Instruction::SkipNeReg { .. } => "9XY0",
+            Instruction::LoadI(_) => "ANNN",
+            Instruction::JumpOffset(_) => "BNNN",
+            Instruction::Random { .. } => "CXNN",
+            Instruction::Draw { .. } => "DXYN",
+            Instruction::SkipKey(_) => "EX9E",
+            Instruction::SkipNotKey(_) => "EXA1",
+            Instruction::LoadLong => "F000",
+            Instruction::SelectPlanes(_) => "FN01",
+            Instruction::LoadAudio => "F002",
+            Instruction::GetDelay(_) => "FX07",
+            Instruction::WaitKey(_) => "FX0A",
+            Instruction::SetDelay(_) => "FX15",
+            Instruction::SetSound(_) => "FX18",
+            Instruction::AddI(_) => "FX1E",
+            Instruction::LoadFont(_) => "FX29",
+            Instruction::LoadBigFont(_) => "FX30",
+            Instruction::Bcd(_) => "FX33",
+            Instruction::SetPitch(_) => "FX3A",
+            Instruction::Store(_) => "FX55",
+            Instruction::Load(_) => "FX65",
+            Instruction::SaveFlags(_) => "FX75",
+            Instruction::LoadFlags(_) => "FX85",
+        }
+    }
+
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Instruction::Clear,
+                0x00EE => Instruction::Return,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::Lores,
+                0x00FF => Instruction::Hires,
+                _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown(n),
+                _ if opcode & 0xFFF0 == 0x00D0 => Instruction::ScrollUp(n),
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jump(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SkipEqImm { x, nn },
+            0x4000 => Instruction::SkipNeImm { x, nn },
+            0x5000 => match n {
+                0x0 => Instruction::SkipEqReg { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x6000 => Instruction::LoadImm { x, nn },
+            0x7000 => Instruction::AddImm { x, nn },
+            0x8000 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::Add { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubReverse { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => return Err(DecodeError { opcode }),
+            },
+            0x9000 if n == 0 => Instruction::SkipNeReg { x, y },
+            0xA000 => Instruction::LoadI(nnn),
+            0xB000 => Instruction::JumpOffset(nnn),
+            0xC000 => Instruction::Random { x, nn },
+            0xD000 => Instruction::Draw { x, y, n },
+            0xE000 => match nn {
+                0x9E => Instruction::SkipKey(x),
+                0xA1 => Instruction::SkipNotKey(x),
+                _ => return Err(DecodeError { opcode }),
+            },
+            0xF000 => match nn {
+                0x00 if x == 0 => Instruction::LoadLong,
+                0x01 => Instruction::SelectPlanes(x as u8),
+                0x02 if x == 0 => Instruction::LoadAudio,
+                0x07 => Instruction::GetDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LoadFont(x),
+                0x30 => Instruction::LoadBigFont(x),
+                0x33 => Instruction::Bcd(x),
+                0x3A => Instruction::SetPitch(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                0x75 => Instruction::SaveFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => return Err(DecodeError { opcode }),
+            },
+            _ => return Err(DecodeError { opcode }),
+        };
+        Ok(instruction)
+    }
+
+    pub fn encode(&self) -> u16 {
+        // packs register indices and immediates back into their nibbles
+        let xy = |prefix: u16, x: usize, y: usize, n: u16| prefix | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |prefix: u16, x: usize, nn: u8| prefix | (x as u16) << 8 | nn as u16;
+
+        match *self {
+            Instruction::ScrollDown(n) => 0x00C0 | n as u16,
+            Instruction::ScrollUp(n) => 0x00D0 | n as u16,
+            Instruction::Clear => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::Sys(nnn) => nnn,
+            Instruction::Jump(nnn) => 0x1000 | nnn,
+            Instruction::Call(nnn) => 0x2000 | nnn,
+            Instruction::SkipEqImm { x, nn } => xnn(0x3000, x, nn),
+            Instruction::SkipNeImm { x, nn } => xnn(0x4000, x, nn),
+            Instruction::SkipEqReg { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadImm { x, nn } => xnn(0x6000, x, nn),
+            Instruction::AddImm { x, nn } => xnn(0x7000, x, nn),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::Add { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubReverse { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNeReg { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI(nnn) => 0xA000 | nnn,
+            Instruction::JumpOffset(nnn) => 0xB000 | nnn,
+            Instruction::Random { x, nn } => xnn(0xC000, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16),
+            Instruction::SkipKey(x) => xnn(0xE000, x, 0x9E),
+            Instruction::SkipNotKey(x) => xnn(0xE000, x, 0xA1),
+            Instruction::LoadLong => 0xF000,
+            Instruction::SelectPlanes(n) => xnn(0xF000, n as usize, 0x01),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::GetDelay(x) => xnn(0xF000, x, 0x07),
+            Instruction::WaitKey(x) => xnn(0xF000, x, 0x0A),
+            Instruction::SetDelay(x) => xnn(0xF000, x, 0x15),
+            Instruction::SetSound(x) => xnn(0xF000, x, 0x18),
+            Instruction::AddI(x) => xnn(0xF000, x, 0x1E),
+            Instruction::LoadFont(x) => xnn(0xF000, x, 0x29),
+            Instruction::LoadBigFont(x) => xnn(0xF000, x, 0x30),
+            Instruction::Bcd(x) => xnn(0xF000, x, 0x33),
+            Instruction::SetPitch(x) => xnn(0xF000, x, 0x3A),
+            Instruction::Store(x) => xnn(0xF000, x, 0x55),
+            Instruction::Load(x) => xnn(0xF000, x, 0x65),
+            Instruction::SaveFlags(x) => xnn(0xF000, x, 0x75),
+            Instruction::LoadFlags(x) => xnn(0xF000, x, 0x85),
+        }
+    }
+}
+
+// assembly mnemonics in the style of Cowgod's Chip-8 technical reference
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::Clear => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEqImm { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNeImm { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::LoadImm { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddImm { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::Move { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubReverse { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpOffset(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Random { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadLong => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::GetDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LoadBigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::SetPitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        // every opcode that decodes must encode back to itself
+        for opcode in 0..=0xFFFFu16 {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{} ({:04X})", instruction, opcode);
+                assert_eq!(Instruction::decode(instruction.encode()), Ok(instruction));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_errors() {
+        for opcode in [0x5001, 0x8008, 0x800F, 0x9001, 0xE000, 0xF100, 0xF102, 0xF0FF] {
+            assert_eq!(Instruction::decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+
+    #[test]
+    fn patterns_match_opcodes() {
+        // the digits of a pattern are the ones every opcode of the instruction has
+        for opcode in 0..=0xFFFFu16 {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                for (index, digit) in instruction.pattern().chars().enumerate() {
+                    if let Some(digit) = digit.to_digit(16) {
+                        assert_eq!((opcode >> (12 - 4 * index)) & 0xF, digit as u16, "{} ({:04X})", instruction.pattern(), opcode);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn display_mnemonics() {
+        assert_eq!(Instruction::decode(0x00E0).unwrap().to_string(), "CLS");
+        assert_eq!(Instruction::decode(0x1228).unwrap().to_string(), "JP 0x228");
+        assert_eq!(Instruction::decode(0x8AB4).unwrap().to_string(), "ADD VA, VB");
+        assert_eq!(Instruction::decode(0xD01F).unwrap().to_string(), "DRW V0, V1, 15");
+        assert_eq!(Instruction::decode(0xF355).unwrap().to_string(), "LD [I], V3");
+    }
+}
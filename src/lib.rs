@@ -0,0 +1,36 @@
+// Frontend independent CHIP-8 interpreter core.
+// The window and audio frontend lives in main.rs and is only built with the "frontend" feature.
+
+// savefile-derive 0.10 generates its impls inside a const block
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate savefile_derive;
+extern crate savefile;
+
+pub mod chip8;
+pub mod opcode_parser;
+pub mod instruction;
+pub mod quirks;
+pub mod fault;
+pub mod rng;
+pub mod fstools;
+pub mod dump;
+pub mod debugger;
+pub mod gdbstub;
+pub mod json;
+pub mod symbols;
+pub mod dap;
+pub mod disasm;
+pub mod asm;
+pub mod trace;
+pub mod tracediff;
+pub mod rewind;
+pub mod history;
+pub mod profile;
+pub mod coverage;
+pub mod condition;
+pub mod bus;
+pub mod observer;
+#[cfg(feature = "cli")]
+pub mod cli;
@@ -1,85 +1,284 @@
-mod chip8;
-mod opcode_parser;
-mod fstools;
 mod input;
 mod audio;
 mod args;
 
-use std::sync::{Arc, RwLock};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
-use crate::args::Rgb;
-use crate::fstools::get_file_as_byte_vec;
-use crate::chip8::Chip8;
-use crate::input::parse_input;
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::asm;
+use chip_8_emu::dap;
+use chip_8_emu::debugger::{self, Debugger, PROMPT};
+use chip_8_emu::fault::FaultPolicy;
+use chip_8_emu::disasm;
+use chip_8_emu::fstools::{get_file_as_byte_vec, load_rom_or_state};
+use chip_8_emu::gdbstub;
+use chip_8_emu::observer::Event;
+use chip_8_emu::rewind::Rewind;
+use chip_8_emu::symbols::SymbolMap;
+use chip_8_emu::coverage::Coverage;
+use chip_8_emu::profile::Profile;
+use chip_8_emu::trace::{Trace, TraceReader};
+use chip_8_emu::tracediff;
 
-#[macro_use]
-extern crate savefile_derive;
-extern crate savefile;
+use crate::args::{Action, Rgb};
+use crate::audio::Pattern;
+use crate::input::parse_input;
 
 extern crate glium;
 
 fn main() {
 
     // args
-    let flags = crate::args::parse_args();
-
-    // setup speed
-    // devide 2 as fetch and decode is on same loop
-    let runhz:u64 = flags.hz;
-    let delay:u64 = 1000/runhz;
-    let satisfiedruntimes: u64 = (1000/60)/delay;
+    let flags = match crate::args::parse_args() {
+        Action::Run(flags) => flags,
+        Action::Dap => {
+            if let Err(err) = dap::serve(std::io::stdin().lock(), Box::new(std::io::stdout())) {
+                eprintln!("Debug adapter error: {}", err);
+            }
+            return;
+        }
+        Action::Disasm { rom_path, output, mode } => {
+            let rom = match get_file_as_byte_vec(&rom_path) {
+                Ok(rom) => rom,
+                Err(err) => {
+                    eprintln!("Unable to read {}: {}", rom_path, err);
+                    std::process::exit(2);
+                }
+            };
+            let listing = disasm::disassemble(&rom, mode);
+            match output {
+                Some(path) => {
+                    if let Err(err) = std::fs::write(&path, listing) {
+                        eprintln!("Failed to write {}: {}", path, err);
+                    }
+                },
+                None => print!("{}", listing),
+            }
+            return;
+        }
+        Action::TraceDiff { paths, context } => {
+            match diff_traces(&paths, context) {
+                Ok(None) => println!("The traces match."),
+                Ok(Some(report)) => {
+                    print!("{}", report);
+                    std::process::exit(1);
+                },
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(2);
+                }
+            }
+            return;
+        }
+        Action::Asm { source_path, output, symbols } => {
+            if let Err(err) = assemble(&source_path, output, symbols) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
 
     // setup cpu instance
     let mut chip8inst = Chip8::new();
-    chip8inst.display = [flags.invert_colors; 2048];
+    chip8inst.set_mode(flags.mode);
+    chip8inst.quirks = flags.quirks;
+    if let Some(seed) = flags.seed {
+        chip8inst.seed_rng(seed);
+    }
+    chip8inst.blank_pixel = flags.invert_colors;
+    chip8inst.display.fill(flags.invert_colors);
+
+    // load the symbols first, so that loading a state already shows the pc relative to them
+    if let Some(path) = &flags.symbols_path {
+        match SymbolMap::load(path) {
+            Ok(symbols) => chip8inst.symbols = symbols,
+            Err(err) => {
+                eprintln!("Unable to load symbols from {}", err);
+                std::process::exit(2);
+            }
+        }
+    }
 
     // load rom/state into chip8inst
-    let rompath = flags.rom_path.as_str();
-    if rompath.ends_with(".state") {
-        crate::fstools::load_state(&std::path::Path::new(rompath).to_path_buf(), &mut chip8inst)
+    let rom_len = match load_rom_or_state(&flags.rom_path, &mut chip8inst) {
+        Ok(rom_len) => rom_len,
+        Err(err) => {
+            eprintln!("Unable to load {}: {}", flags.rom_path, err);
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = &flags.trace_path {
+        match Trace::create(path, flags.trace_format, flags.trace_filter) {
+            Ok(trace) => chip8inst.trace = Some(trace),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = &flags.profile_path {
+        match Profile::create(path) {
+            Ok(profile) => chip8inst.profile = Some(profile),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = &flags.coverage_path {
+        match Coverage::create(path, rom_len) {
+            Ok(coverage) => chip8inst.coverage = Some(coverage),
+            Err(err) => {
+                eprintln!("Unable to create {}: {}", path, err);
+                std::process::exit(2);
+            }
+        }
     }
-    else {
-        chip8inst.load_program(&get_file_as_byte_vec(rompath));
+
+    // the beeper follows the sound events instead of polling the sound timer, a loaded state may already be sounding
+    let sounding = Arc::new(AtomicBool::new(chip8inst.sound_timer > 0));
+    let observersounding = sounding.clone();
+    chip8inst.set_observer(Some(Box::new(move |_: &Chip8, event| match event {
+        Event::SoundStarted => observersounding.store(true, Ordering::Relaxed),
+        Event::SoundStopped => observersounding.store(false, Ordering::Relaxed),
+        _ => {}
+    })));
+
+    // the debugger pauses the emulation thread, and is controlled from a prompt or gdb on threads of their own
+    // watchpoints alone run with the prompt until one of them pauses
+    let prompt = flags.debug || !flags.watches.is_empty();
+    let mut debugger = None;
+    if prompt || flags.gdb_port.is_some() {
+        let mut watcher = Debugger::new(flags.ipf);
+        for spec in &flags.watches {
+            if let Err(err) = watcher.watch(&chip8inst, spec) {
+                eprintln!("Invalid watchpoint '{}': {}", spec, err);
+                std::process::exit(2);
+            }
+        }
+        if !flags.debug && flags.gdb_port.is_none() {
+            watcher.resume();
+        }
+        debugger = Some(watcher);
     }
     let chip8arc = Arc::new(RwLock::new(chip8inst));
+    let debuggerarc = debugger.map(|debugger| Arc::new(Mutex::new(debugger)));
+    if let Some(debugger) = &debuggerarc {
+        if prompt {
+            spawn_debugger_prompt(chip8arc.clone(), debugger.clone());
+        }
+        if let Some(port) = flags.gdb_port {
+            let gdbchip8 = chip8arc.clone();
+            let gdbdebugger = debugger.clone();
+            println!("Waiting for gdb on port {}", port);
+            std::thread::spawn(move || {
+                if let Err(err) = gdbstub::listen(port, gdbchip8, gdbdebugger) {
+                    println!("gdb connection failed: {}", err);
+                }
+            });
+        }
+    }
+
+    // set by the input handler while the rewind key is held
+    let rewinding = Arc::new(AtomicBool::new(false));
 
     let loopchip8 = chip8arc.clone();
+    let looprewinding = rewinding.clone();
     std::thread::spawn(move || {
+        let mut rewind = Rewind::new(flags.rewind_depth, flags.rewind_interval);
         let beeper = crate::audio::Beeper::new(flags.vol);
         let beeperexist = beeper.is_ok() && flags.vol > 0.0;
         if !beeperexist {
             println!("Audio not initialized!");
         }
 
-        let mut runtimes = 0;
+        // frames are paced against the time the loop started, so that sleeping inaccuracies don't add up
+        let frame_nanos: u64 = 1_000_000_000 / 60;
+        let mut start_time = std::time::Instant::now();
+        let mut frames: u64 = 0;
+        // the fault that ended the last frame
+        let mut last_fault = None;
         loop {
-            let next_frame_time = std::time::Instant::now() + std::time::Duration::from_millis(delay);
-            
-            // timer stuff
-            if runtimes >= satisfiedruntimes {
-                if loopchip8.read().unwrap().delay_timer > 0 {
-                    loopchip8.write().unwrap().delay_timer -= 1;
-                }
-                if loopchip8.read().unwrap().sound_timer > 0 {
-                    if beeperexist {
-                        beeper.as_ref().unwrap().play();
+            frames += 1;
+            let next_frame_time = start_time + std::time::Duration::from_nanos(frames * 1_000_000_000 / 60);
+
+            {
+                let mut chip8 = loopchip8.write().unwrap();
+
+                // while the rewind key is held, step back a snapshot each frame instead of running
+                let rewound = looprewinding.load(Ordering::Relaxed) && rewind.step_back(&mut chip8);
+
+                // run cpu and timers for a frame, the debugger stops at breakpoints and faults instead
+                let mut paused = rewound;
+                let result = match &debuggerarc {
+                    _ if rewound => Ok(()),
+                    Some(debugger) => {
+                        let mut debugger = debugger.lock().unwrap();
+                        if debugger.quit {
+                            write_logs(&mut chip8);
+                            std::process::exit(0);
+                        }
+                        let was_paused = debugger.paused;
+                        match debugger.run_frame(&mut chip8) {
+                            _ if !prompt => {},
+                            Ok(true) if !was_paused => {
+                                print!("{}\n{}\n{}", debugger.stop_message(&chip8), debugger::stop_report(&chip8, &debugger), PROMPT);
+                            },
+                            Err(fault) => {
+                                print!("CPU fault: {}, paused.\n{}\n{}", fault.describe(&chip8.symbols), debugger::stop_report(&chip8, &debugger), PROMPT);
+                            },
+                            _ => {}
+                        }
+                        let _ = std::io::stdout().flush();
+                        paused = debugger.paused;
+                        Ok(())
+                    },
+                    None => chip8.run_frame(flags.ipf)
+                };
+                if let Err(fault) = result {
+                    match flags.fault_policy {
+                        // the same fault in the next frame would repeat forever, e.g. with the pc past the end of memory
+                        _ if last_fault == Some(fault) => {
+                            println!("CPU fault: {} repeated, halting.", fault.describe(&chip8.symbols));
+                            chip8.halted = true;
+                        },
+                        FaultPolicy::Halt => {
+                            println!("CPU fault: {}, halting.", fault.describe(&chip8.symbols));
+                            chip8.halted = true;
+                        },
+                        FaultPolicy::Log => println!("CPU fault: {}", fault.describe(&chip8.symbols)),
+                        FaultPolicy::Ignore => {}
                     }
-                    loopchip8.write().unwrap().sound_timer -= 1;
                 }
-                else if beeperexist {
-                    beeper.as_ref().unwrap().pause();
+                last_fault = result.err();
+                if !paused && !chip8.halted {
+                    rewind.frame(&chip8);
                 }
 
-                runtimes = 0;
+                if beeperexist {
+                    if sounding.load(Ordering::Relaxed) && !paused {
+                        let pattern = chip8.audio_pattern.map(|buffer| Pattern { buffer, pitch: chip8.pitch });
+                        beeper.as_ref().unwrap().set_pattern(pattern);
+                        beeper.as_ref().unwrap().play();
+                    }
+                    else {
+                        beeper.as_ref().unwrap().pause();
+                    }
+                }
             }
-            runtimes += 1;
-
 
-            // cycle cpu
-            loopchip8.write().unwrap().single_cycle();
-
-            if next_frame_time > std::time::Instant::now() {
-                std::thread::sleep(next_frame_time - std::time::Instant::now());
+            let now = std::time::Instant::now();
+            if next_frame_time > now {
+                std::thread::sleep(next_frame_time - now);
+            }
+            else if now - next_frame_time > std::time::Duration::from_nanos(frame_nanos) {
+                // fell more than a frame behind, start pacing from now instead of running frames back to back
+                start_time = now;
+                frames = 0;
             }
         }
     });
@@ -97,7 +296,8 @@ fn main() {
         let next_frame_time = std::time::Instant::now() + std::time::Duration::from_nanos(16_666_667);
         
         if last_next_frame_time <= std::time::Instant::now() {
-            render_texture_to_target(&eventloopchip8.read().unwrap().display, &display, &flags.fg, &flags.bg);
+            let chip8 = eventloopchip8.read().unwrap();
+            render_texture_to_target(&chip8.display, chip8.display_width(), chip8.display_height(), &display, &flags.palette);
             last_next_frame_time = next_frame_time;
         }
 
@@ -105,12 +305,13 @@ fn main() {
         match ev {
             glutin::event::Event::WindowEvent { event, .. } => match event {
                 glutin::event::WindowEvent::CloseRequested => {
+                    write_logs(&mut eventloopchip8.write().unwrap());
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
                     return;
                 },
                 glutin::event::WindowEvent::KeyboardInput { device_id: _, input, is_synthetic: _ } => {
                     // println!("{:?}", input.virtual_keycode.unwrap());
-                    parse_input(input, &mut eventloopchip8.write().unwrap(), &flags);
+                    parse_input(input, &mut eventloopchip8.write().unwrap(), &flags, &rewinding);
                 },
                 _ => return,
             },
@@ -119,14 +320,70 @@ fn main() {
     });
 }
 
-fn render_texture_to_target(dispmem: &[u8; 2048], display: &glium::Display, fg: &Rgb, bg: &Rgb) {
+// reads debugger commands from stdin and runs them on the live chip8
+fn spawn_debugger_prompt(chip8arc: Arc<RwLock<Chip8>>, debugger: Arc<Mutex<Debugger>>) {
+    std::thread::spawn(move || {
+        {
+            let chip8 = chip8arc.read().unwrap();
+            let debugger = debugger.lock().unwrap();
+            if debugger.paused {
+                print!("Paused, type 'help' for a list of commands.\n{}\n{}", debugger::stop_report(&chip8, &debugger), PROMPT);
+                let _ = std::io::stdout().flush();
+            }
+        }
+
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break
+            };
+
+            // same lock order as the emulation thread
+            let mut chip8 = chip8arc.write().unwrap();
+            let mut debugger = debugger.lock().unwrap();
+            let output = debugger.command(&mut chip8, &line);
+            if debugger.quit {
+                write_logs(&mut chip8);
+                std::process::exit(0);
+            }
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            print!("{}", PROMPT);
+            let _ = std::io::stdout().flush();
+        }
+    });
+}
+
+// the trace is buffered and the profile and coverage are only written at the end, the process exits without dropping them
+fn write_logs(chip8: &mut Chip8) {
+    if let Some(trace) = &mut chip8.trace {
+        if let Err(err) = trace.flush() {
+            eprintln!("Unable to write the trace: {}", err);
+        }
+    }
+    if let Some(profile) = &chip8.profile {
+        if let Err(err) = profile.write() {
+            eprintln!("Unable to write the profile: {}", err);
+        }
+    }
+    if let Some(coverage) = &chip8.coverage {
+        if let Err(err) = coverage.write() {
+            eprintln!("Unable to write the coverage: {}", err);
+        }
+    }
+}
+
+fn render_texture_to_target(dispmem: &[u8], width: usize, height: usize, display: &glium::Display, palette: &[Rgb; 4]) {
     use crate::glium::Surface;
 
-    let mut disptexturevec = vec![vec![(bg.r, bg.g, bg.b); 64]; 32];
+    // textures are stored bottom row first, the display memory is stored column by column
+    // each pixel's plane bitmask picks its color from the palette
+    let bg = palette[0];
+    let mut disptexturevec = vec![vec![(bg.r, bg.g, bg.b); width]; height];
     for i in  0..dispmem.len() {
-        if dispmem[i] == 1 {
-            disptexturevec[31 - (i % 32)][i / 32] = (fg.r, fg.g, fg.b);
-        }
+        let color = palette[(dispmem[i] & 0x3) as usize];
+        disptexturevec[height - 1 - (i % height)][i / height] = (color.r, color.g, color.b);
     }
     let texture = glium::Texture2d::new(display, disptexturevec).unwrap();
 
@@ -134,4 +391,33 @@ fn render_texture_to_target(dispmem: &[u8; 2048], display: &glium::Display, fg:
     target.clear_color(0.0, 0.0, 0.0, 1.0);
     texture.as_surface().fill(&target, glium::uniforms::MagnifySamplerFilter::Nearest);
     target.finish().unwrap();
-}
\ No newline at end of file
+}
+// writes the ROM assembled from source_path, and the symbol map next to it if asked for
+fn assemble(source_path: &str, output: Option<String>, symbols: bool) -> Result<(), String> {
+    let source = std::fs::read_to_string(source_path).map_err(|err| format!("{}: {}", source_path, err))?;
+    let mut program = asm::assemble(&source).map_err(|err| format!("{}: {}", source_path, err))?;
+    let rom_path = output.map(PathBuf::from).unwrap_or_else(|| Path::new(source_path).with_extension("ch8"));
+    std::fs::write(&rom_path, &program.rom).map_err(|err| format!("{}: {}", rom_path.display(), err))?;
+    println!("Assembled {} bytes into {}", program.rom.len(), rom_path.display());
+
+    if symbols {
+        let symbols_path = rom_path.with_extension("sym");
+        // the source is named relative to the symbol map when they share a directory
+        let source = Path::new(source_path);
+        program.symbols.source = Some(if source.parent() == symbols_path.parent() {
+            source.file_name().unwrap().to_string_lossy().into_owned()
+        }
+        else {
+            std::fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf()).to_string_lossy().into_owned()
+        });
+        std::fs::write(&symbols_path, program.symbols.to_string()).map_err(|err| format!("{}: {}", symbols_path.display(), err))?;
+    }
+    return Ok(());
+}
+
+fn diff_traces(paths: &[String; 2], context: usize) -> Result<Option<String>, String> {
+    let open = |path: &String| {
+        std::fs::File::open(path).and_then(|file| TraceReader::new(std::io::BufReader::new(file))).map_err(|err| format!("{}: {}", path, err))
+    };
+    return tracediff::diff(&mut open(&paths[0])?, &mut open(&paths[1])?, [&paths[0], &paths[1]], context);
+}
@@ -0,0 +1,40 @@
+// Notable things the interpreter does, for frontends and tools that react to them instead of polling the state.
+//
+// The observer installed with Chip8::set_observer is told about every event as it happens, with the state as it is
+// right after the instruction or timer tick that caused it. It runs on the thread that runs the cpu, so it should
+// hand anything slow, like rendering or audio, off to another thread.
+
+use crate::chip8::Chip8;
+use crate::fault::CpuFault;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    // the display changed: 00E0 cleared it, DXYN drew a sprite, it was scrolled or switched between lo-res and hi-res,
+    // or a state was restored
+    Drawn,
+    // the sound timer was set from 0, or restored from a state, the buzzer or audio pattern should start playing
+    SoundStarted,
+    // the sound timer ran out or was set to 0, by an instruction or a restored state
+    SoundStopped,
+    // FX0A started waiting for a key press and release, which goes to VX
+    KeyWait(usize),
+    // 2NNN at pc called the subroutine at addr
+    Call { pc: u16, addr: u16 },
+    // 00EE at pc returned to addr
+    Return { pc: u16, addr: u16 },
+    // the opcode at pc isn't part of the instruction set
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // any other fault that stopped an instruction
+    Fault(CpuFault),
+}
+
+pub trait Observer {
+    fn notify(&mut self, chip8: &Chip8, event: Event);
+}
+
+// closures make observers too
+impl<F: FnMut(&Chip8, Event)> Observer for F {
+    fn notify(&mut self, chip8: &Chip8, event: Event) {
+        self(chip8, event)
+    }
+}
@@ -1,296 +1,378 @@
-use crate::chip8::Chip8;
-use rand::Rng;
+use crate::chip8::{Chip8, Mode, BIG_FONT_ADDR};
+use crate::fault::CpuFault;
+use crate::instruction::Instruction;
 
-pub fn parse_op(chip8: &mut Chip8) {
-    let x = ((chip8.opcode & 0x0F00) >> 8) as usize;
-    let y = ((chip8.opcode & 0x00F0) >> 4) as usize;
-    let nn = (chip8.opcode & 0x00FF) as u8;
+// fetches a byte of the instruction at pc through the bus, faulting instead of panicking when addr is out of bounds
+fn fetch(chip8: &mut Chip8, pc: u16, addr: usize) -> Result<u8, CpuFault> {
+    chip8.fetch_byte(addr).ok_or(CpuFault::MemoryOutOfBounds { pc, addr })
+}
 
-    // println!("{:X}", chip8.opcode);
-    match chip8.opcode  {
-        0x00E0 => {
-            chip8.display = [0; 2048];
-            return;
+// reads a byte of memory through the bus, faulting instead of panicking when addr is out of bounds
+fn read(chip8: &mut Chip8, pc: u16, addr: usize) -> Result<u8, CpuFault> {
+    chip8.read_byte(addr).ok_or(CpuFault::MemoryOutOfBounds { pc, addr })
+}
+
+// writes a byte of memory through the bus, faulting instead of panicking when addr is out of bounds
+fn write(chip8: &mut Chip8, pc: u16, addr: usize, value: u8) -> Result<(), CpuFault> {
+    if chip8.write_byte(addr, value) { Ok(()) } else { Err(CpuFault::MemoryOutOfBounds { pc, addr }) }
+}
+
+// skips the next instruction, which is 4 bytes long if it is the XO-CHIP F000 NNNN long load
+fn skip_next(chip8: &mut Chip8) {
+    let pc = chip8.pc as usize;
+    if chip8.mode == Mode::XoChip && chip8.fetch_byte(pc) == Some(0xF0) && chip8.fetch_byte(pc + 1) == Some(0x00) {
+        chip8.pc = chip8.pc.wrapping_add(4);
+    }
+    else {
+        chip8.pc = chip8.pc.wrapping_add(2);
+    }
+}
+
+pub fn execute(chip8: &mut Chip8, instruction: Instruction) -> Result<(), CpuFault> {
+    // the address of the instruction being executed, pc has already been moved past it
+    let pc = chip8.pc.wrapping_sub(2);
+
+    // 0NNN machine code calls are ignored, other instructions from instruction sets that aren't enabled are unknown
+    if !instruction.supported_by(chip8.mode) {
+        if instruction.encode() & 0xF000 == 0 {
+            return Ok(());
+        }
+        return Err(CpuFault::UnknownOpcode { pc, opcode: instruction.encode() });
+    }
+
+    match instruction {
+        Instruction::Clear => {
+            // clears the selected planes, which is only ever the first one outside of XO-CHIP
+            let planes = chip8.planes;
+            chip8.display.iter_mut().for_each(|pixel| *pixel &= !planes);
         },
-        0x00EE => {
+        Instruction::Return => {
             // sets pc to the address at the top of the stack
-            chip8.pc = chip8.jumpstack[chip8.stackpointer as usize];
+            if chip8.stackpointer == 0 {
+                return Err(CpuFault::StackUnderflow { pc });
+            }
             chip8.stackpointer -= 1;
-            return;
+            chip8.pc = chip8.jumpstack[chip8.stackpointer as usize];
         },
-        _ => {}
-    }
-
-    // matches opcode with last 3 nibbles removed (ie, 0xA22A -> 0xA000)
-    match chip8.opcode & 0xF000 {
-        0x0000 => {
+        Instruction::ScrollDown(n) => {
+            chip8.scroll(0, n as isize);
+        },
+        Instruction::ScrollUp(n) => {
+            chip8.scroll(0, -(n as isize));
+        },
+        Instruction::ScrollRight => {
+            chip8.scroll(4, 0);
+        },
+        Instruction::ScrollLeft => {
+            chip8.scroll(-4, 0);
+        },
+        Instruction::Exit => {
+            chip8.halted = true;
+        },
+        Instruction::Lores => {
+            chip8.set_hires(false);
+        },
+        Instruction::Hires => {
+            chip8.set_hires(true);
+        },
+        Instruction::Sys(_) => {
             // ignored by modern interpreters
-            return;
         },
-        0x1000 => {
-            // 1NNN - jump to address NNN
-            chip8.pc = chip8.opcode & 0x0FFF;
-            return;
-        }
-        0x2000 => {
-            // 2NNN - call subroutine at NNN
-            chip8.stackpointer += 1;
+        Instruction::Jump(nnn) => {
+            chip8.pc = nnn;
+        },
+        Instruction::Call(nnn) => {
+            // pushes the return address onto the stack
+            if chip8.stackpointer as usize >= chip8.jumpstack.len() {
+                return Err(CpuFault::StackOverflow { pc });
+            }
             chip8.jumpstack[chip8.stackpointer as usize] = chip8.pc;
-            chip8.pc = chip8.opcode & 0x0FFF;
-            return;
-        }
-        0x3000 => {
-            // 3XNN - skip next instruction if VX == NN
+            chip8.stackpointer += 1;
+            chip8.pc = nnn;
+        },
+        Instruction::SkipEqImm { x, nn } => {
             if chip8.vregisters[x] == nn {
-                chip8.pc += 2;
+                skip_next(chip8);
             }
-        }
-        0x4000 => {
-            // 4XNN - skip next instruction if VX != NN
+        },
+        Instruction::SkipNeImm { x, nn } => {
             if chip8.vregisters[x] != nn {
-                chip8.pc += 2;
+                skip_next(chip8);
             }
-            return;
-        }
-        0x6000 => {
-            // 6XNN - set VX to NN
-            chip8.vregisters[x] = nn;
-            return;
-        }
-        0x7000 => {
-            // 7XNN - add NN to VX
-            chip8.vregisters[x] = (chip8.vregisters[x] as u16 + nn as u16) as u8;
-            return;
-        },
-        0xA000 => {
-            // ANNN - set I to NNN
-            chip8.i = chip8.opcode & 0x0FFF;
-            return;
-        },
-        0xB000 => {
-            // BNNN - jump to address NNN + V0
-            chip8.pc = (chip8.opcode & 0x0FFF) + chip8.vregisters[0] as u16;
-            return;
-        },
-        0xC000 => {
-            // CXNN - set VX to random byte ANDed with NN
-            chip8.vregisters[x] = nn & rand::thread_rng().gen::<u8>();
-            return;
-        },
-        0xD000 => {
-            // DXYN - draw sprite at VX, VY with N bytes of sprite data starting at I
-            let width = 8;
-            let nbytes = chip8.opcode & 0x000F;
-
-            // vregisters at x and y
-            let vx = chip8.vregisters[x];
-            let vy = chip8.vregisters[y];
-
-            // set last register to 0
-            chip8.vregisters[0xF] = 0;
-
-            for row in 0..nbytes {
-                // get the sprite from memory
-                let mut sprt = chip8.memory[(chip8.i + row) as usize];
-
-                for col in 0..width {
-                    // if the sprite is not 0
-                    if sprt & 0x0080 > 0 {
-                        let disppixel = &mut chip8.display[(
-                            (vy as u16 + row) % 32 + 
-                            (vx as u16 + col) % 64 * 
-                            32
-                        ) as usize];
-
-                        // set last register to 1 if pixel is set
-                        if *disppixel == 1 {
-                            chip8.vregisters[0xF] = 1;
-                        }
-
-                        // toggle pixel
-                        *disppixel ^= 1;
-                    }
-
-                    // shift the sprite to the right to be ready for next draw
-                    sprt <<= 1;
-                }
-            }
-            return;
         },
-        _ => {}
-    }
-
-    // matches first and last nibbles (ie, 0xA22A -> 0xA00A) (mostly alu v-register operations)
-    match chip8.opcode & 0xF00F {
-        0x5000 => {
-            // 5XY0 - skip next instruction if VX == VY
+        Instruction::SkipEqReg { x, y } => {
             if chip8.vregisters[x] == chip8.vregisters[y] {
-                chip8.pc += 2;
+                skip_next(chip8);
             }
-            return;
         },
-        0x8000 => {
-            // 8XY0 - set VX to VY
-            chip8.vregisters[x] = chip8.vregisters[y];
-            return;
+        Instruction::SaveRange { x, y } => {
+            // stored in reverse order if X > Y
+            let count = x.abs_diff(y);
+            for offset in 0..=count {
+                let index = if x <= y { x + offset } else { x - offset };
+                write(chip8, pc, chip8.i as usize + offset, chip8.vregisters[index])?;
+            }
         },
-        0x8001 => {
-            // 8XY1 - set VX to VX | VY
-            chip8.vregisters[x] |= chip8.vregisters[y];
-            return;
+        Instruction::LoadRange { x, y } => {
+            // read in reverse order if X > Y
+            let count = x.abs_diff(y);
+            for offset in 0..=count {
+                let index = if x <= y { x + offset } else { x - offset };
+                chip8.vregisters[index] = read(chip8, pc, chip8.i as usize + offset)?;
+            }
         },
-        0x8002 => {
-            // 8XY2 - set VX to VX & VY
-            chip8.vregisters[x] &= chip8.vregisters[y];
-            return;
+        Instruction::LoadImm { x, nn } => {
+            chip8.vregisters[x] = nn;
         },
-        0x8003 => {
-            // 8XY3 - set VX to VX ^ VY
-            chip8.vregisters[x] ^= chip8.vregisters[y];
-            return;
+        Instruction::AddImm { x, nn } => {
+            chip8.vregisters[x] = chip8.vregisters[x].wrapping_add(nn);
         },
-        0x8004 => {
-            // 8XY4 - set VF to 1 if carry, set VX to VX + VY
-
-            // Checks if the hex nibbles plussed together uses more than 8 bits, meaning it has carried over.
-            let result = chip8.vregisters[x] as u16 + chip8.vregisters[y] as u16;
-            if result > 0x00FF {
-                chip8.vregisters[0xF] = 1;
-            }
-            else {
+        Instruction::Move { x, y } => {
+            chip8.vregisters[x] = chip8.vregisters[y];
+        },
+        Instruction::Or { x, y } => {
+            chip8.vregisters[x] |= chip8.vregisters[y];
+            if chip8.quirks.vf_reset {
                 chip8.vregisters[0xF] = 0;
             }
-            chip8.vregisters[x] = chip8.vregisters[x].wrapping_add(chip8.vregisters[y]);
-            return;
         },
-        0x8005 => {
-            // 8XY5 - set VF to 0 if borrow, set VX to VX - VY
-            if chip8.vregisters[x] > chip8.vregisters[y] {
-                chip8.vregisters[0xF] = 1;
-            }
-            else {
+        Instruction::And { x, y } => {
+            chip8.vregisters[x] &= chip8.vregisters[y];
+            if chip8.quirks.vf_reset {
                 chip8.vregisters[0xF] = 0;
             }
-
-            chip8.vregisters[x] = chip8.vregisters[x].wrapping_sub(chip8.vregisters[y]);
-            return;
-        },
-        0x8006 => {
-            // 8XY6 - set VF to LSB of VX, set VX to VX >> 1
-
-            // Set VF to least significant bit of VX
-            chip8.vregisters[0xF] = chip8.vregisters[x] & 0x01;
-
-            chip8.vregisters[x] >>= 1;
-
-            return;
         },
-        0x8007 => {
-            // 8XY7 - set VX to VY - VX, set VF to 0 if borrow
-            if chip8.vregisters[y] > chip8.vregisters[x] {
-                chip8.vregisters[0xF] = 1;
-            }
-            else {
+        Instruction::Xor { x, y } => {
+            chip8.vregisters[x] ^= chip8.vregisters[y];
+            if chip8.quirks.vf_reset {
                 chip8.vregisters[0xF] = 0;
             }
-
-            chip8.vregisters[x] -= chip8.vregisters[y];
-            return;
         },
-        0x800E => {
-            // 8XYE - set VX to VX << 1, set VF to MSB of VX
+        Instruction::Add { x, y } => {
+            // VF is set after the result, so that it holds the carry even when X is F
+            let (result, carry) = chip8.vregisters[x].overflowing_add(chip8.vregisters[y]);
+            chip8.vregisters[x] = result;
+            chip8.vregisters[0xF] = carry as u8;
+        },
+        Instruction::Sub { x, y } => {
+            // VF is 1 when there is no borrow, including when VX == VY
+            let (result, borrow) = chip8.vregisters[x].overflowing_sub(chip8.vregisters[y]);
+            chip8.vregisters[x] = result;
+            chip8.vregisters[0xF] = !borrow as u8;
+        },
+        Instruction::ShiftRight { x, y } => {
+            // without the shifting quirk, VY is shifted into VX instead
+            let value = if chip8.quirks.shifting { chip8.vregisters[x] } else { chip8.vregisters[y] };
 
-            // set registers by pushing unneeded bits off, and leaving with the MSB
-            chip8.vregisters[0xF] = chip8.vregisters[x] >> 7;
+            // VF is set to the bit that was shifted out
+            chip8.vregisters[x] = value >> 1;
+            chip8.vregisters[0xF] = value & 0x01;
+        },
+        Instruction::SubReverse { x, y } => {
+            // VF is 1 when there is no borrow, including when VY == VX
+            let (result, borrow) = chip8.vregisters[y].overflowing_sub(chip8.vregisters[x]);
+            chip8.vregisters[x] = result;
+            chip8.vregisters[0xF] = !borrow as u8;
+        },
+        Instruction::ShiftLeft { x, y } => {
+            // without the shifting quirk, VY is shifted into VX instead
+            let value = if chip8.quirks.shifting { chip8.vregisters[x] } else { chip8.vregisters[y] };
 
-            chip8.vregisters[x] <<= 1;
-            return;
+            // VF is set to the bit that was shifted out
+            chip8.vregisters[x] = value << 1;
+            chip8.vregisters[0xF] = value >> 7;
         },
-        0x9000 => {
-            // 9XY0 - skip next instruction if VX != VY
+        Instruction::SkipNeReg { x, y } => {
             if chip8.vregisters[x] != chip8.vregisters[y] {
-                chip8.pc += 2;
+                skip_next(chip8);
             }
-            return;
         },
-        _ => {}
-    }
-
-    match chip8.opcode & 0xF0FF {
-        0xE09E => {
-            // EX9E - skip next instruction if key in VX is pressed
-            if chip8.keystate[chip8.vregisters[x] as usize] != 0 {
-                chip8.pc += 2;
+        Instruction::LoadI(nnn) => {
+            chip8.i = nnn;
+        },
+        Instruction::JumpOffset(nnn) => {
+            // BXNN - jump to address XNN + VX (jumping quirk)
+            let offset = if chip8.quirks.jumping { chip8.vregisters[(nnn >> 8) as usize] } else { chip8.vregisters[0] };
+            chip8.pc = nnn + offset as u16;
+        },
+        Instruction::Random { x, nn } => {
+            chip8.vregisters[x] = nn & chip8.random_byte();
+        },
+        Instruction::Draw { x, y, n } => {
+            draw(chip8, pc, x, y, n)?;
+        },
+        Instruction::SkipKey(x) => {
+            if chip8.keystate[(chip8.vregisters[x] & 0xF) as usize] != 0 {
+                skip_next(chip8);
             }
-            return;
         },
-        0xE0A1 => {
-            // EXA1 - skip next instruction if key in VX is not pressed
-            if chip8.keystate[chip8.vregisters[x] as usize] == 0 {
-                chip8.pc += 2;
+        Instruction::SkipNotKey(x) => {
+            if chip8.keystate[(chip8.vregisters[x] & 0xF) as usize] == 0 {
+                skip_next(chip8);
+            }
+        },
+        Instruction::LoadLong => {
+            // the address is stored in the 2 bytes after the opcode
+            let addr = chip8.pc as usize;
+            chip8.i = (fetch(chip8, pc, addr)? as u16) << 8 | fetch(chip8, pc, addr + 1)? as u16;
+            chip8.pc = chip8.pc.wrapping_add(2);
+        },
+        Instruction::SelectPlanes(n) => {
+            chip8.planes = n & 0x3;
+        },
+        Instruction::LoadAudio => {
+            let mut pattern = [0; 16];
+            for (index, byte) in pattern.iter_mut().enumerate() {
+                *byte = read(chip8, pc, chip8.i as usize + index)?;
             }
-            return;
+            chip8.audio_pattern = Some(pattern);
         },
-        0xF007 => {
-            // FX07 - set VX to delay timer value
+        Instruction::GetDelay(x) => {
             chip8.vregisters[x] = chip8.delay_timer;
-            return;
-        },
-        0xF00A => {
-            // FX0A - wait for keypress, store in VX
-            // todo: maybe broken!
-            match chip8.keystate.iter().position(|&x| x != 0) {
-                Some(key) => {
-                    chip8.vregisters[x] = key as u8;
+        },
+        Instruction::WaitKey(x) => {
+            // waits for a key to be pressed and then released, like the original interpreter
+            match chip8.waiting_key {
+                Some(key) if chip8.keystate[key as usize] == 0 => {
+                    chip8.vregisters[x] = key;
+                    chip8.waiting_key = None;
+                },
+                Some(_) => {
+                    chip8.pc = pc;
                 },
                 None => {
-                    chip8.pc -= 2;
+                    chip8.waiting_key = chip8.keystate.iter().position(|&x| x != 0).map(|key| key as u8);
+                    chip8.pc = pc;
                 }
             }
-            return;
         },
-        0xF015 => {
-            // FX15 - set delay timer to VX
+        Instruction::SetDelay(x) => {
             chip8.delay_timer = chip8.vregisters[x];
-            return;
         },
-        0xF018 => {
-            // FX18 - set sound timer to VX
+        Instruction::SetSound(x) => {
             chip8.sound_timer = chip8.vregisters[x];
-            return;
         },
-        0xF01E => {
-            // FX1E - add VX to I, set to I
-            chip8.i += chip8.vregisters[x] as u16;
-            return;
+        Instruction::AddI(x) => {
+            chip8.i = chip8.i.wrapping_add(chip8.vregisters[x] as u16);
         },
-        0xF029 => {
-            // FX29 - set I to location of sprite for digit VX
+        Instruction::LoadFont(x) => {
             // multiplied by 5, as each sprite is 5 bytes long
             chip8.i = chip8.vregisters[x] as u16 * 5;
-            return;
-        },
-        0xF033 => {
-            // FX33 - store BCD representation of VX in memory locations I, I+1, and I+2
-            chip8.memory[chip8.i as usize] = (chip8.vregisters[x] / 100) % 10;
-            chip8.memory[(chip8.i + 1) as usize] = (chip8.vregisters[x] / 10) % 10;
-            chip8.memory[(chip8.i + 2) as usize] = chip8.vregisters[x] % 10;
-            return;
-        },
-        0xF055 => {
-            // FX55 - store V0 to VX in memory starting at address I
-            for index in 0..x {
-                chip8.memory[chip8.i as usize + index] = chip8.vregisters[index];
+        },
+        Instruction::LoadBigFont(x) => {
+            // multiplied by 10, as each large sprite is 10 bytes long
+            chip8.i = BIG_FONT_ADDR + (chip8.vregisters[x] & 0x0F) as u16 * 10;
+        },
+        Instruction::Bcd(x) => {
+            write(chip8, pc, chip8.i as usize, (chip8.vregisters[x] / 100) % 10)?;
+            write(chip8, pc, chip8.i as usize + 1, (chip8.vregisters[x] / 10) % 10)?;
+            write(chip8, pc, chip8.i as usize + 2, chip8.vregisters[x] % 10)?;
+        },
+        Instruction::SetPitch(x) => {
+            chip8.pitch = chip8.vregisters[x];
+        },
+        Instruction::Store(x) => {
+            for index in 0..=x {
+                write(chip8, pc, chip8.i as usize + index, chip8.vregisters[index])?;
+            }
+            if chip8.quirks.memory_increment {
+                chip8.i = chip8.i.wrapping_add(x as u16 + 1);
             }
-            return;
         },
-        0xF065 => {
-            // FX65 - read V0 to VX from memory starting at address I
-            for index in 0..x {
-                chip8.vregisters[index] = chip8.memory[chip8.i as usize + index];
+        Instruction::Load(x) => {
+            for index in 0..=x {
+                chip8.vregisters[index] = read(chip8, pc, chip8.i as usize + index)?;
+            }
+            if chip8.quirks.memory_increment {
+                chip8.i = chip8.i.wrapping_add(x as u16 + 1);
+            }
+        },
+        Instruction::SaveFlags(x) => {
+            for index in 0..=x {
+                chip8.rpl_flags[index] = chip8.vregisters[index];
+            }
+        },
+        Instruction::LoadFlags(x) => {
+            for index in 0..=x {
+                chip8.vregisters[index] = chip8.rpl_flags[index];
             }
-            return;
         },
-        _ => {}
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// DXYN - draw sprite at VX, VY with N bytes of sprite data starting at I
+// DXY0 - draw a 16x16 sprite at VX, VY with 32 bytes of sprite data (SUPER-CHIP)
+fn draw(chip8: &mut Chip8, pc: u16, x: usize, y: usize, n: u8) -> Result<(), CpuFault> {
+    let nbytes = n as u16;
+    let (width, height) = if nbytes == 0 && chip8.mode != Mode::Chip8 { (16, 16) } else { (8, nbytes) };
+
+    // wait for the vertical blank before drawing (display wait quirk)
+    if chip8.quirks.display_wait {
+        if !chip8.vblank_interrupt {
+            chip8.pc = pc;
+            return Ok(());
+        }
+        chip8.vblank_interrupt = false;
+    }
+
+    let dispwidth = chip8.display_width() as u16;
+    let dispheight = chip8.display_height() as u16;
+
+    // vregisters at x and y, the starting position always wraps around the screen
+    let vx = chip8.vregisters[x] as u16 % dispwidth;
+    let vy = chip8.vregisters[y] as u16 % dispheight;
+
+    // set last register to 0
+    chip8.vregisters[0xF] = 0;
+
+    // each selected plane is drawn with its own sprite data, stored one after another starting at I
+    let spritelen = if width == 16 { height * 2 } else { height };
+    let mut spriteaddr = chip8.i as usize;
+
+    for plane in [1u8, 2u8] {
+        if chip8.planes & plane == 0 {
+            continue;
+        }
+
+        for row in 0..height {
+            // get the sprite row from memory, left aligned in 16 bits
+            let mut sprt = if width == 16 {
+                (read(chip8, pc, spriteaddr + row as usize * 2)? as u16) << 8 | read(chip8, pc, spriteaddr + row as usize * 2 + 1)? as u16
+            }
+            else {
+                (read(chip8, pc, spriteaddr + row as usize)? as u16) << 8
+            };
+
+            for col in 0..width {
+                // pixels past the edge of the screen are dropped (clipping quirk)
+                if chip8.quirks.clipping && (vx + col >= dispwidth || vy + row >= dispheight) {
+                    break;
+                }
+
+                // if the sprite is not 0
+                if sprt & 0x8000 > 0 {
+                    let disppixel = &mut chip8.display[(
+                        (vy + row) % dispheight + 
+                        (vx + col) % dispwidth * 
+                        dispheight
+                    ) as usize];
+
+                    // set last register to 1 if pixel is set
+                    if *disppixel & plane != 0 {
+                        chip8.vregisters[0xF] = 1;
+                    }
+
+                    // toggle pixel
+                    *disppixel ^= plane;
+                }
+
+                // shift the sprite to the right to be ready for next draw
+                sprt <<= 1;
+            }
+        }
+
+        spriteaddr += spritelen as usize;
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+// Counts where a program spends its instructions, for finding what to optimize in a ROM.
+//
+// Every executed instruction is counted by its address and by its opcode pattern. Calls and returns keep a stack of
+// the subroutines running, which gives each subroutine an exclusive count of the instructions in its own body and an
+// inclusive count that adds everything it called. The report lists the busiest addresses, the instruction mix and
+// the call graph. The folded stacks have a line per call path with the instructions spent in it, the input
+// flamegraph.pl and inferno-flamegraph take.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use crate::chip8::Chip8;
+use crate::instruction::Instruction;
+
+// the amount of addresses the report lists
+pub const TOP_ADDRESSES: usize = 20;
+
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+// a frame of the call stack is the address of the subroutine, None for the code running before any call
+type Frame = Option<u16>;
+
+#[derive(Default)]
+pub struct Profile {
+    // where the report is written, the folded stacks go next to it
+    path: Option<PathBuf>,
+    instructions: u64,
+    // the count and the last opcode seen at each address
+    addresses: HashMap<u16, (u64, u16)>,
+    patterns: HashMap<&'static str, u64>,
+    // the subroutines running and the instruction count when each was entered
+    stack: Vec<Frame>,
+    entered: Vec<u64>,
+    functions: HashMap<Frame, Counts>,
+    // (caller, callee), exclusive counts aren't kept for them
+    calls: HashMap<(Frame, Frame), Counts>,
+    folded: HashMap<Vec<Frame>, u64>,
+}
+
+fn frame_name(frame: Frame) -> String {
+    match frame {
+        Some(addr) => format!("sub_{:03X}", addr),
+        None => "start".to_string(),
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 }
+}
+
+// adds the instructions run since the frame at depth was entered to the inclusive counts of it and its call
+fn close(stack: &[Frame], entered: &[u64], depth: usize, now: u64, functions: &mut HashMap<Frame, Counts>, calls: &mut HashMap<(Frame, Frame), Counts>) {
+    let frame = stack[depth];
+    let elapsed = now - entered[depth];
+    // the instructions of a recursive call are already part of the outer one
+    if !stack[..depth].contains(&frame) {
+        functions.entry(frame).or_default().inclusive += elapsed;
+    }
+    if depth > 0 {
+        calls.entry((stack[depth - 1], frame)).or_default().inclusive += elapsed;
+    }
+}
+
+impl Profile {
+    // counts without writing anything, the report can be read back with report and folded
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    // the report is created now so that a path that can't be written is found before running
+    pub fn create(path: &str) -> io::Result<Self> {
+        File::create(path)?;
+        Ok(Profile { path: Some(PathBuf::from(path)), ..Profile::default() })
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // called after every executed instruction, pc being the address it was fetched from
+    pub fn record(&mut self, chip8: &Chip8, pc: u16, instruction: Instruction) {
+        if self.stack.is_empty() {
+            self.enter(None);
+        }
+        self.instructions += 1;
+        let address = self.addresses.entry(pc).or_insert((0, 0));
+        address.0 += 1;
+        address.1 = chip8.opcode;
+        *self.patterns.entry(instruction.pattern()).or_default() += 1;
+        self.functions.entry(*self.stack.last().unwrap()).or_default().exclusive += 1;
+        match self.folded.get_mut(&self.stack) {
+            Some(count) => *count += 1,
+            None => {
+                self.folded.insert(self.stack.clone(), 1);
+            },
+        }
+
+        match instruction {
+            Instruction::Call(nnn) => self.enter(Some(nnn)),
+            // frames the stack pointer no longer covers are left, a state loaded with calls running has none to leave
+            Instruction::Return => {
+                while self.stack.len() > chip8.stackpointer as usize + 1 && self.stack.len() > 1 {
+                    self.leave();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn enter(&mut self, frame: Frame) {
+        self.functions.entry(frame).or_default().calls += 1;
+        if let Some(&caller) = self.stack.last() {
+            self.calls.entry((caller, frame)).or_default().calls += 1;
+        }
+        self.stack.push(frame);
+        self.entered.push(self.instructions);
+    }
+
+    fn leave(&mut self) {
+        close(&self.stack, &self.entered, self.stack.len() - 1, self.instructions, &mut self.functions, &mut self.calls);
+        self.stack.pop();
+        self.entered.pop();
+    }
+
+    // the counts with the subroutines still running closed as of now
+    fn closed(&self) -> (HashMap<Frame, Counts>, HashMap<(Frame, Frame), Counts>) {
+        let mut functions = self.functions.clone();
+        let mut calls = self.calls.clone();
+        for depth in (0..self.stack.len()).rev() {
+            close(&self.stack, &self.entered, depth, self.instructions, &mut functions, &mut calls);
+        }
+        (functions, calls)
+    }
+
+    pub fn report(&self) -> String {
+        let total = self.instructions;
+        let mut out = format!("{} instructions\n", total);
+
+        let mut addresses: Vec<(&u16, &(u64, u16))> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(b.0)));
+        out.push_str("\nTop addresses:\n  address       count        %  opcode  instruction\n");
+        for (addr, (count, opcode)) in addresses.into_iter().take(TOP_ADDRESSES) {
+            let mnemonic = Instruction::decode(*opcode).map(|instruction| instruction.to_string()).unwrap_or_default();
+            let _ = writeln!(out, "  0x{:03X}   {:>11}  {:>6.2}%  {:04X}    {}", addr, count, percent(*count, total), opcode, mnemonic);
+        }
+
+        let mut patterns: Vec<(&&str, &u64)> = self.patterns.iter().collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        out.push_str("\nInstruction mix:\n  opcode        count        %\n");
+        for (pattern, count) in patterns {
+            let _ = writeln!(out, "  {}    {:>11}  {:>6.2}%", pattern, count, percent(*count, total));
+        }
+
+        // each subroutine is followed by the ones that called it (<-) and the ones it called (->)
+        let (functions, calls) = self.closed();
+        let mut frames: Vec<(&Frame, &Counts)> = functions.iter().collect();
+        frames.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        let mut calls: Vec<(&(Frame, Frame), &Counts)> = calls.iter().collect();
+        calls.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+        out.push_str("\nCall graph:\n  subroutine        calls    inclusive        %    exclusive        %\n");
+        for (frame, counts) in frames {
+            let _ = writeln!(out, "  {:<12} {:>10}  {:>11}  {:>6.2}%  {:>11}  {:>6.2}%", frame_name(*frame), counts.calls,
+                counts.inclusive, percent(counts.inclusive, total), counts.exclusive, percent(counts.exclusive, total));
+            for ((caller, _), edge) in calls.iter().filter(|((_, callee), _)| callee == frame) {
+                let _ = writeln!(out, "    <- {:<9} {:>9}  {:>11}", frame_name(*caller), edge.calls, edge.inclusive);
+            }
+            for ((_, callee), edge) in calls.iter().filter(|((caller, _), _)| caller == frame) {
+                let _ = writeln!(out, "    -> {:<9} {:>9}  {:>11}", frame_name(*callee), edge.calls, edge.inclusive);
+            }
+        }
+        out
+    }
+
+    // a line per call path with the instructions spent in its last subroutine, e.g. "start;sub_2A4 120"
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.folded.iter()
+            .map(|(stack, count)| format!("{} {}", stack.iter().map(|frame| frame_name(*frame)).collect::<Vec<String>>().join(";"), count))
+            .collect();
+        lines.sort();
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    // writes the report and the folded stacks next to it with a '.folded' extension, if the profile has a path
+    pub fn write(&self) -> io::Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, self.report())?;
+            std::fs::write(path.with_extension("folded"), self.folded())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+// Behaviours that differ between CHIP-8 interpreters over the years.
+// A quirk set to true means the interpreter behaves like the original hardware
+// that introduced the quirk, as described in the names of each field.
+
+#[derive(Savefile, Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place and ignore VY (CHIP-48/SCHIP), instead of VX = VY shifted (COSMAC VIP)
+    pub shifting: bool,
+
+    // FX55/FX65 increment I by X + 1 after storing/loading
+    pub memory_increment: bool,
+
+    // BNNN is treated as BXNN, jumping to XNN + VX instead of NNN + V0
+    pub jumping: bool,
+
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+
+    // DXYN clips sprites at the edges of the screen instead of wrapping them around
+    pub clipping: bool,
+
+    // DXYN waits for the next vertical blank before drawing, limiting draws to one per frame
+    pub display_wait: bool,
+}
+
+pub static PRESET_NAMES: [&str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+impl Quirks {
+    // original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shifting: false,
+            memory_increment: true,
+            jumping: false,
+            vf_reset: true,
+            clipping: true,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Self {
+        Quirks {
+            shifting: true,
+            memory_increment: true,
+            jumping: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn schip() -> Self {
+        Quirks {
+            shifting: true,
+            memory_increment: false,
+            jumping: true,
+            vf_reset: false,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Self {
+        Quirks {
+            shifting: false,
+            memory_increment: true,
+            jumping: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+
+    // what most modern interpreters (and this emulator, by default) do
+    pub fn modern() -> Self {
+        Quirks {
+            shifting: true,
+            memory_increment: false,
+            jumping: false,
+            vf_reset: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_preset(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Quirks::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::schip()),
+            "xochip" | "xo-chip" => Some(Quirks::xochip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::modern()
+    }
+}
@@ -0,0 +1,122 @@
+// Rewinding keeps a bounded history of snapshots of the machine to step back through.
+//
+// Snapshots are the state as save states serialize it. Every KEYFRAME_INTERVAL-th snapshot is kept whole, the
+// ones in between only keep the runs of bytes that differ from their keyframe, which for most frames is a handful
+// of registers, timers and the few bytes of memory and display that changed.
+
+use std::collections::VecDeque;
+
+use savefile::{load_noschema, save_noschema};
+
+use crate::chip8::{Chip8, STATE_VERSION};
+
+pub const KEYFRAME_INTERVAL: usize = 60;
+
+// the bytes of a snapshot that differ from its keyframe, as (offset, bytes) runs
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn new(keyframe: &[u8], snapshot: &[u8]) -> Self {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (offset, (&key, &byte)) in keyframe.iter().zip(snapshot).enumerate() {
+            if key == byte {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == offset => bytes.push(byte),
+                _ => runs.push((offset, vec![byte])),
+            }
+        }
+        Delta { len: snapshot.len(), runs }
+    }
+
+    fn apply(&self, keyframe: &[u8]) -> Vec<u8> {
+        let mut snapshot = keyframe[..self.len].to_vec();
+        for (offset, bytes) in &self.runs {
+            snapshot[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        snapshot
+    }
+}
+
+// a keyframe and the snapshots taken after it
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Delta>,
+}
+
+pub struct Rewind {
+    // the most snapshots kept, rounded up to whole keyframe groups
+    depth: usize,
+    // frames between snapshots
+    interval: u32,
+    frames: u32,
+    groups: VecDeque<Group>,
+    len: usize,
+}
+
+impl Rewind {
+    pub fn new(depth: usize, interval: u32) -> Self {
+        Rewind { depth, interval: interval.max(1), frames: 0, groups: VecDeque::new(), len: 0 }
+    }
+
+    // the amount of snapshots that can be stepped back through
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // called after every frame that ran, takes a snapshot every interval frames
+    pub fn frame(&mut self, chip8: &Chip8) {
+        self.frames += 1;
+        if self.frames < self.interval || self.depth == 0 {
+            return;
+        }
+        self.frames = 0;
+
+        let mut snapshot = Vec::new();
+        if save_noschema(&mut snapshot, STATE_VERSION, chip8).is_err() {
+            return;
+        }
+        // a snapshot that changed size, e.g. after switching modes, can't be a delta
+        match self.groups.back_mut() {
+            Some(group) if group.deltas.len() + 1 < KEYFRAME_INTERVAL && group.keyframe.len() == snapshot.len() => {
+                group.deltas.push(Delta::new(&group.keyframe, &snapshot));
+            },
+            _ => self.groups.push_back(Group { keyframe: snapshot, deltas: Vec::new() }),
+        }
+        self.len += 1;
+
+        // the oldest group is dropped once the others hold enough snapshots on their own
+        while self.groups.len() > 1 && self.len - (self.groups[0].deltas.len() + 1) >= self.depth {
+            let group = self.groups.pop_front().unwrap();
+            self.len -= group.deltas.len() + 1;
+        }
+    }
+
+    // restores the most recent snapshot and forgets it, false when there is none left
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let group = match self.groups.back_mut() {
+            Some(group) => group,
+            None => return false,
+        };
+        let snapshot = match group.deltas.pop() {
+            Some(delta) => delta.apply(&group.keyframe),
+            None => self.groups.pop_back().unwrap().keyframe,
+        };
+        self.len -= 1;
+        self.frames = 0;
+
+        let state: Chip8 = match load_noschema(&mut &snapshot[..], STATE_VERSION) {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        chip8.restore_from(state).is_ok()
+    }
+}
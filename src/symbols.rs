@@ -0,0 +1,128 @@
+// Symbol maps tie addresses in a ROM back to the labels and source lines they were assembled from.
+//
+// The file format has one entry per line, blank lines and lines starting with '#' are ignored:
+//   <label> <address>         a label, e.g. "main 0x200"
+//   :line <line> <address>    the first instruction assembled from a line of the source
+//   :source <path>            the source file that the lines refer to, relative to the symbol file
+// Label files exported by other tools are read too, with the address first or the label written as in Octo
+// source, e.g. "0x200 main" or ": main 0x200".
+//
+// Addresses are shown as the closest label before them and the offset from it, e.g. "main+0x4".
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolMap {
+    pub source: Option<String>,
+    pub labels: BTreeMap<String, u16>,
+    // source line to address
+    pub lines: BTreeMap<u32, u16>,
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<SymbolMap, String> {
+        let mut map = SymbolMap::default();
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            match fields.as_slice() {
+                [] => {},
+                [first, ..] if first.starts_with('#') => {},
+                [":source", path] => map.source = Some(path.to_string()),
+                [":line", line, addr] => {
+                    let line = line.parse::<u32>().map_err(|_| error("invalid line number"))?;
+                    map.lines.insert(line, parse_address(addr).ok_or_else(|| error("invalid address"))?);
+                },
+                [":", name, addr] => {
+                    map.labels.insert(name.to_string(), parse_address(addr).ok_or_else(|| error("invalid address"))?);
+                },
+                [addr, name] if !name.starts_with(':') && parse_address(addr).is_some() && parse_address(name).is_none() => {
+                    map.labels.insert(name.to_string(), parse_address(addr).unwrap());
+                },
+                [name, addr] if !name.starts_with(':') => {
+                    map.labels.insert(name.to_string(), parse_address(addr).ok_or_else(|| error("invalid address"))?);
+                },
+                _ => return Err(error("expected '<label> <address>', ':line <line> <address>' or ':source <path>'")),
+            }
+        }
+        Ok(map)
+    }
+
+    // the symbol map next to a ROM with a '.sym' extension, as the assembler writes it, if there is one
+    pub fn path_for_rom(rom_path: &str) -> Option<PathBuf> {
+        let path = Path::new(rom_path).with_extension("sym");
+        if path.is_file() { Some(path) } else { None }
+    }
+
+    pub fn load(path: &Path) -> Result<SymbolMap, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        SymbolMap::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // the first source line at or after line that has code, and its address
+    pub fn address_of_line(&self, line: u32) -> Option<(u32, u16)> {
+        self.lines.range(line..).next().map(|(&line, &addr)| (line, addr))
+    }
+
+    // the source line of the instruction at addr, the closest line starting before it
+    pub fn line_of_address(&self, addr: u16) -> Option<u32> {
+        self.lines.iter()
+            .filter(|&(_, &start)| start <= addr)
+            .max_by_key(|&(&line, &start)| (start, line))
+            .map(|(&line, _)| line)
+    }
+
+    // the closest label at or before addr, and how far past it addr is
+    pub fn label_before(&self, addr: u16) -> Option<(&str, u16)> {
+        self.labels.iter()
+            .filter(|&(_, &start)| start <= addr)
+            .max_by_key(|&(_, &start)| start)
+            .map(|(name, &start)| (name.as_str(), addr - start))
+    }
+
+    // addr as the closest label before it and the offset from it, e.g. "main+0x4", or in hex without one
+    pub fn describe(&self, addr: u16) -> String {
+        match self.label_before(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+0x{:X}", name, offset),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    // the address of a label with an optional offset, e.g. "main" or "main+0x4", or of a number
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        if let Some(addr) = parse_address(text) {
+            return Some(addr);
+        }
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, parse_address(offset)?),
+            None => (text, 0),
+        };
+        self.labels.get(name)?.checked_add(offset)
+    }
+}
+
+// writes the map in the format parse reads
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(source) = &self.source {
+            writeln!(f, ":source {}", source)?;
+        }
+        for (name, addr) in &self.labels {
+            writeln!(f, "{} 0x{:03X}", name, addr)?;
+        }
+        for (line, addr) in &self.lines {
+            writeln!(f, ":line {} 0x{:03X}", line, addr)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u16>().ok(),
+    }
+}
@@ -0,0 +1,307 @@
+// Logs every executed instruction with the machine state after it, for comparing runs against other emulators.
+//
+// The text form has a line per instruction, blank lines and lines starting with '#' are ignored:
+//   <cycle> <pc> <opcode> <v0-vf> <i> <sp> <write> [mnemonic]
+// Numbers other than the cycle are hex, v0-vf being 32 digits. <write> is '-' when the instruction didn't write
+// memory, or the address and bytes it wrote, e.g. "0300=010203". The mnemonic is only for reading and is ignored.
+// Traces from other emulators can be compared against ours once converted to this form.
+//
+// The binary form starts with BINARY_MAGIC, followed by a RECORD_SIZE byte little endian record per instruction:
+//   cycle u64, pc u16, opcode u16, i u16, sp u8, number of bytes written u8, v0-vf
+// and when bytes were written, the address they were written at as a u16 and the bytes.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Read, Write};
+
+use crate::chip8::Chip8;
+use crate::instruction::Instruction;
+
+pub const BINARY_MAGIC: &[u8; 4] = b"C8T1";
+pub const RECORD_SIZE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+pub static FORMAT_NAMES: [&str; 2] = ["text", "binary"];
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(TraceFormat::Text),
+            "binary" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+// the state after one instruction, pc being the address the instruction was fetched from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub vregisters: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    // the address and bytes the instruction wrote to memory
+    pub write: Option<(u16, Vec<u8>)>,
+}
+
+impl TraceRecord {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.i.to_le_bytes());
+        bytes[14] = self.sp;
+        bytes[16..32].copy_from_slice(&self.vregisters);
+        if let Some((addr, written)) = &self.write {
+            bytes[15] = written.len() as u8;
+            bytes.extend_from_slice(&addr.to_le_bytes());
+            bytes.extend_from_slice(written);
+        }
+        bytes
+    }
+
+    // reads the next record of a binary trace, None at the end
+    pub fn read_binary(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut bytes = [0; RECORD_SIZE];
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {},
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut vregisters = [0; 16];
+        vregisters.copy_from_slice(&bytes[16..32]);
+        let write = match bytes[15] {
+            0 => None,
+            len => {
+                let mut addr = [0; 2];
+                reader.read_exact(&mut addr)?;
+                let mut written = vec![0; len as usize];
+                reader.read_exact(&mut written)?;
+                Some((u16::from_le_bytes(addr), written))
+            },
+        };
+        Ok(Some(TraceRecord {
+            cycle: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pc: u16::from_le_bytes([bytes[8], bytes[9]]),
+            opcode: u16::from_le_bytes([bytes[10], bytes[11]]),
+            vregisters,
+            i: u16::from_le_bytes([bytes[12], bytes[13]]),
+            sp: bytes[14],
+            write,
+        }))
+    }
+
+    // parses a line of a text trace, None for blank lines and comments
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let fields: Vec<&str> = line.split_whitespace().take(7).collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            return Ok(None);
+        }
+        if fields.len() < 7 {
+            return Err("expected '<cycle> <pc> <opcode> <v0-vf> <i> <sp> <write>'".to_string());
+        }
+        let hex = |field: &str, name: &str| u16::from_str_radix(field, 16).map_err(|_| format!("invalid {} '{}'", name, field));
+        let bytes = |field: &str, name: &str| -> Result<Vec<u8>, String> {
+            let invalid = || format!("invalid {} '{}'", name, field);
+            if !field.len().is_multiple_of(2) {
+                return Err(invalid());
+            }
+            // the field is read from outside, a pair of bytes can split a multibyte character
+            (0..field.len()).step_by(2).map(|at| u8::from_str_radix(field.get(at..at + 2).ok_or_else(invalid)?, 16).map_err(|_| invalid())).collect()
+        };
+
+        let mut vregisters = [0; 16];
+        let values = bytes(fields[3], "registers")?;
+        if values.len() != 16 {
+            return Err(format!("expected 16 registers, found {}", values.len()));
+        }
+        vregisters.copy_from_slice(&values);
+        let write = match fields[6] {
+            "-" => None,
+            field => {
+                let (addr, written) = field.split_once('=').ok_or_else(|| format!("invalid write '{}'", field))?;
+                Some((hex(addr, "write address")?, bytes(written, "write")?))
+            },
+        };
+        Ok(Some(TraceRecord {
+            cycle: fields[0].parse::<u64>().map_err(|_| format!("invalid cycle '{}'", fields[0]))?,
+            pc: hex(fields[1], "pc")?,
+            opcode: hex(fields[2], "opcode")?,
+            vregisters,
+            i: hex(fields[4], "I")?,
+            sp: u8::from_str_radix(fields[5], 16).map_err(|_| format!("invalid sp '{}'", fields[5]))?,
+            write,
+        }))
+    }
+}
+
+// a line of the text form
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X} {:04X} ", self.cycle, self.pc, self.opcode)?;
+        for v in self.vregisters {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(f, " {:04X} {:02X} ", self.i, self.sp)?;
+        match &self.write {
+            Some((addr, written)) => {
+                write!(f, "{:04X}=", addr)?;
+                for byte in written {
+                    write!(f, "{:02X}", byte)?;
+                }
+            },
+            None => write!(f, "-")?,
+        }
+        match Instruction::decode(self.opcode) {
+            Ok(instruction) => write!(f, " {}", instruction),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+// reads the records of a trace in either form, telling them apart by the binary form's magic
+pub struct TraceReader<R: BufRead> {
+    reader: R,
+    format: TraceFormat,
+    // line number of the last line read from a text trace
+    line: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let format = if reader.fill_buf()?.starts_with(BINARY_MAGIC) {
+            reader.consume(BINARY_MAGIC.len());
+            TraceFormat::Binary
+        }
+        else {
+            TraceFormat::Text
+        };
+        Ok(TraceReader { reader, format, line: 0 })
+    }
+
+    pub fn next_record(&mut self) -> Result<Option<TraceRecord>, String> {
+        match self.format {
+            TraceFormat::Binary => TraceRecord::read_binary(&mut self.reader).map_err(|err| err.to_string()),
+            TraceFormat::Text => loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+                    return Ok(None);
+                }
+                self.line += 1;
+                if let Some(record) = TraceRecord::parse(&line).map_err(|err| format!("line {}: {}", self.line, err))? {
+                    return Ok(Some(record));
+                }
+            },
+        }
+    }
+}
+
+// which instructions are written to the trace, all instructions are still counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceFilter {
+    // inclusive range of instruction addresses
+    pub pcs: Option<(u16, u16)>,
+    // first cycle and the cycle to stop before
+    pub cycles: (u64, u64),
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter { pcs: None, cycles: (0, u64::MAX) }
+    }
+}
+
+impl TraceFilter {
+    pub fn matches(&self, cycle: u64, pc: u16) -> bool {
+        let (first, end) = self.cycles;
+        (first..end).contains(&cycle) && self.pcs.is_none_or(|(low, high)| (low..=high).contains(&pc))
+    }
+
+    // parses "START-END" with hex addresses, e.g. "0x200-0x2FF"
+    pub fn parse_pcs(text: &str) -> Option<(u16, u16)> {
+        let (low, high) = text.split_once('-')?;
+        let parse = |x: &str| {
+            let x = x.trim();
+            u16::from_str_radix(x.strip_prefix("0x").or_else(|| x.strip_prefix("0X")).unwrap_or(x), 16).ok()
+        };
+        Some((parse(low)?, parse(high)?)).filter(|(low, high)| low <= high)
+    }
+
+    // parses "FIRST-END", where either end can be left out, e.g. "1000-" or "-500"
+    pub fn parse_cycles(text: &str) -> Option<(u64, u64)> {
+        let (first, end) = text.split_once('-')?;
+        let first = if first.trim().is_empty() { 0 } else { first.trim().parse::<u64>().ok()? };
+        let end = if end.trim().is_empty() { u64::MAX } else { end.trim().parse::<u64>().ok()? };
+        Some((first, end)).filter(|(first, end)| first <= end)
+    }
+}
+
+pub struct Trace {
+    out: Box<dyn Write + Send + Sync>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    // instructions executed since tracing started
+    cycle: u64,
+    // the first write error, tracing stops after it
+    error: Option<io::Error>,
+}
+
+impl Trace {
+    pub fn new(mut out: Box<dyn Write + Send + Sync>, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        match format {
+            TraceFormat::Text => writeln!(out, "# cycle pc opcode v0-vf i sp write instruction")?,
+            TraceFormat::Binary => out.write_all(BINARY_MAGIC)?,
+        }
+        Ok(Trace { out, format, filter, cycle: 0, error: None })
+    }
+
+    pub fn create(path: &str, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        Trace::new(Box::new(BufWriter::new(File::create(path)?)), format, filter)
+    }
+
+    // called after every executed instruction, with the value I had before it
+    pub fn record(&mut self, chip8: &Chip8, pc: u16, instruction: Instruction, i: u16) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.error.is_some() || !self.filter.matches(cycle, pc) {
+            return;
+        }
+        let write = match instruction.bytes_written() {
+            0 => None,
+            // a bus can take writes past the end of memory, only the bytes that are in memory are recorded
+            len => {
+                let end = (i as usize + len).min(chip8.memory.len());
+                chip8.memory.get(i as usize..end).filter(|bytes| !bytes.is_empty()).map(|bytes| (i, bytes.to_vec()))
+            },
+        };
+        let record = TraceRecord {
+            cycle,
+            pc,
+            opcode: instruction.encode(),
+            vregisters: chip8.vregisters,
+            i: chip8.i,
+            sp: chip8.stackpointer as u8,
+            write,
+        };
+        let result = match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", record),
+            TraceFormat::Binary => self.out.write_all(&record.to_bytes()),
+        };
+        self.error = result.err();
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+}
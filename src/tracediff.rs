@@ -0,0 +1,103 @@
+// Finds the first instruction where two traces diverge, matching their records up by cycle.
+//
+// Records are compared on pc, opcode, registers, I, the stack pointer and memory writes. A cycle that only one
+// of the traces has is a divergence too, which is how a run leaving the traced pc range shows up.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io::BufRead;
+
+use crate::trace::{TraceReader, TraceRecord};
+
+// the fields that differ between two records of the same cycle, as "<field>: <a> vs <b>"
+pub fn differences(a: &TraceRecord, b: &TraceRecord) -> Vec<String> {
+    let mut out = Vec::new();
+    if a.pc != b.pc {
+        out.push(format!("pc: {:04X} vs {:04X}", a.pc, b.pc));
+    }
+    if a.opcode != b.opcode {
+        out.push(format!("opcode: {:04X} vs {:04X}", a.opcode, b.opcode));
+    }
+    for (index, (va, vb)) in a.vregisters.iter().zip(b.vregisters.iter()).enumerate() {
+        if va != vb {
+            out.push(format!("V{:X}: {:02X} vs {:02X}", index, va, vb));
+        }
+    }
+    if a.i != b.i {
+        out.push(format!("I: {:04X} vs {:04X}", a.i, b.i));
+    }
+    if a.sp != b.sp {
+        out.push(format!("sp: {:02X} vs {:02X}", a.sp, b.sp));
+    }
+    if a.write != b.write {
+        let describe = |write: &Option<(u16, Vec<u8>)>| match write {
+            Some((addr, bytes)) => format!("{:04X}={}", addr, bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<String>()),
+            None => "-".to_string(),
+        };
+        out.push(format!("write: {} vs {}", describe(&a.write), describe(&b.write)));
+    }
+    out
+}
+
+fn next<R: BufRead>(reader: &mut TraceReader<R>, name: &str) -> Result<Option<TraceRecord>, String> {
+    reader.next_record().map_err(|err| format!("{}: {}", name, err))
+}
+
+// adds the diverging record of a trace and the ones that follow it to the report
+fn after<R: BufRead>(report: &mut String, name: &str, first: Option<TraceRecord>, reader: &mut TraceReader<R>, context: usize) -> Result<(), String> {
+    let _ = writeln!(report, "\n{}:", name);
+    match first {
+        Some(record) => {
+            let _ = writeln!(report, "> {}", record);
+            for _ in 0..context {
+                match next(reader, name)? {
+                    Some(record) => {
+                        let _ = writeln!(report, "  {}", record);
+                    },
+                    None => break,
+                }
+            }
+        },
+        None => report.push_str("  (end of trace)\n"),
+    }
+    Ok(())
+}
+
+// a report of the first divergence with context records around it, None if the traces match
+pub fn diff<A: BufRead, B: BufRead>(a: &mut TraceReader<A>, b: &mut TraceReader<B>, names: [&str; 2], context: usize) -> Result<Option<String>, String> {
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut record_a = next(a, names[0])?;
+    let mut record_b = next(b, names[1])?;
+    let summary = loop {
+        match (&record_a, &record_b) {
+            (None, None) => return Ok(None),
+            (Some(ra), None) => break format!("{} ends before cycle {}", names[1], ra.cycle),
+            (None, Some(rb)) => break format!("{} ends before cycle {}", names[0], rb.cycle),
+            (Some(ra), Some(rb)) if ra.cycle < rb.cycle => break format!("cycle {} is only in {}", ra.cycle, names[0]),
+            (Some(ra), Some(rb)) if ra.cycle > rb.cycle => break format!("cycle {} is only in {}", rb.cycle, names[1]),
+            (Some(ra), Some(rb)) => {
+                let found = differences(ra, rb);
+                if !found.is_empty() {
+                    break format!("cycle {} differs:\n  {}", ra.cycle, found.join("\n  "));
+                }
+            },
+        }
+        before.push_back(record_a.take().unwrap());
+        if before.len() > context {
+            before.pop_front();
+        }
+        record_a = next(a, names[0])?;
+        record_b = next(b, names[1])?;
+    };
+
+    let mut report = format!("First divergence: {}\n", summary);
+    if !before.is_empty() {
+        report.push_str("\nBefore, in both:\n");
+        for record in &before {
+            let _ = writeln!(report, "  {}", record);
+        }
+    }
+    after(&mut report, names[0], record_a, a, context)?;
+    after(&mut report, names[1], record_b, b, context)?;
+    Ok(Some(report))
+}
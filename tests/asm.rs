@@ -0,0 +1,70 @@
+// Assembles the sources of the test ROMs and checks them against the committed ROMs, and checks the error reporting.
+
+use std::path::PathBuf;
+
+use chip_8_emu::asm::assemble;
+use chip_8_emu::symbols::SymbolMap;
+
+fn path(file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "roms", file].iter().collect()
+}
+
+#[test]
+fn test_roms_match_their_sources() {
+    for name in ["logo", "opcodes", "flags", "quirks", "keypad"] {
+        let source = std::fs::read_to_string(path(&format!("{}.8o", name))).unwrap();
+        let program = assemble(&source).unwrap_or_else(|err| panic!("{}.8o: {}", name, err));
+        assert_eq!(program.rom, std::fs::read(path(&format!("{}.ch8", name))).unwrap(), "{}.8o", name);
+    }
+}
+
+#[test]
+fn control_flow() {
+    let source = "
+        :const count 3
+        :alias counter v1
+        : data 0b11110000 0x90
+        : main
+          counter := 0
+          loop
+            counter += 1
+            while counter != count
+            if v0 key then v2 := 1
+          again
+          i := data
+          sub
+        : sub
+          return
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(program.rom, [
+        0x12, 0x04, 0xF0, 0x90,
+        0x61, 0x00,
+        0x71, 0x01, 0x41, 0x03, 0x12, 0x12, 0xE0, 0xA1, 0x62, 0x01, 0x12, 0x06,
+        0xA2, 0x02, 0x22, 0x16,
+        0x00, 0xEE,
+    ]);
+    assert_eq!(program.symbols.labels["main"], 0x204);
+    assert_eq!(program.symbols.address_of_line(6), Some((6, 0x204)));
+
+    // the symbol map reads back the same
+    assert_eq!(SymbolMap::parse(&program.symbols.to_string()).unwrap(), program.symbols);
+}
+
+#[test]
+fn errors_have_line_numbers() {
+    let cases = [
+        (": main\n  v0 := 0x100\n", 2, "0x100 doesn't fit in 8 bits"),
+        (": main\n\n  jump nowhere\n", 3, "undefined name 'nowhere'"),
+        (": main\n  loop\n  v0 += 1\n", 2, "'loop' without an 'again'"),
+        (": main\n  :org 0x1000\n  loop\n  again\n", 3, "'loop' is past 0xFFF"),
+        (": main\n  :org 0xFFC\n  loop\n  while v0 == 1\n  again\n", 3, "the end of the 'loop' is past 0xFFF"),
+        (": main\n  sprite v0 v1\n", 2, "unexpected end of source"),
+        (": main\n  : main\n", 2, "'main' is already defined"),
+        ("v0 := 1\n", 1, "no ': main' label to start the program at"),
+    ];
+    for (source, line, message) in cases {
+        let err = assemble(source).err().unwrap_or_else(|| panic!("{:?} assembled", source));
+        assert_eq!((err.line, err.message.as_str()), (line, message), "{:?}", source);
+    }
+}
@@ -0,0 +1,106 @@
+// Installs buses that count, serve and refuse memory accesses, and checks the interpreter goes through them.
+
+use std::sync::{Arc, Mutex};
+
+use chip_8_emu::bus::{Bus, FlatMemory};
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::fault::CpuFault;
+
+#[derive(Default)]
+struct Counts {
+    fetches: Vec<usize>,
+    reads: Vec<usize>,
+    writes: Vec<usize>,
+}
+
+// counts the accesses, leaving memory to the default implementation
+struct Counter(Arc<Mutex<Counts>>);
+
+impl Bus for Counter {
+    fn fetch(&mut self, memory: &[u8], addr: usize) -> Option<u8> {
+        self.0.lock().unwrap().fetches.push(addr);
+        memory.get(addr).copied()
+    }
+
+    fn read(&mut self, memory: &[u8], addr: usize) -> Option<u8> {
+        self.0.lock().unwrap().reads.push(addr);
+        memory.get(addr).copied()
+    }
+
+    fn write(&mut self, memory: &mut [u8], addr: usize, value: u8) -> bool {
+        self.0.lock().unwrap().writes.push(addr);
+        memory[addr] = value;
+        true
+    }
+}
+
+// a device past the end of memory that counts up on every read of 0x1000, and refuses writes to the ROM at 0x200
+struct Device {
+    counter: u8,
+    rom_end: usize,
+}
+
+impl Bus for Device {
+    fn read(&mut self, memory: &[u8], addr: usize) -> Option<u8> {
+        if addr == 0x1000 {
+            self.counter += 1;
+            return Some(self.counter);
+        }
+        memory.get(addr).copied()
+    }
+
+    fn write(&mut self, memory: &mut [u8], addr: usize, value: u8) -> bool {
+        if (0x200..self.rom_end).contains(&addr) {
+            return false;
+        }
+        FlatMemory.write(memory, addr, value)
+    }
+}
+
+#[test]
+fn counts_accesses() {
+    // stores the BCD of 123 at 0x300, loads and draws the font sprite of its first digit and skips the last instruction
+    let program = [0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x65, 0xF0, 0x29, 0xD0, 0x02, 0x30, 0x01, 0x00, 0xE0];
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&program).unwrap();
+    chip8.set_bus(Some(Box::new(Counter(counts.clone()))));
+    for _ in 0..7 {
+        chip8.single_cycle().unwrap();
+    }
+    assert_eq!(chip8.pc, 0x210);
+    assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+    assert_eq!(chip8.vregisters[..2], [1, 2]);
+
+    let counts = counts.lock().unwrap();
+    assert_eq!(counts.fetches, (0x200..0x20E).collect::<Vec<usize>>());
+    assert_eq!(counts.writes, [0x300, 0x301, 0x302]);
+    assert_eq!(counts.reads, [0x300, 0x301, 0x005, 0x006]);
+}
+
+#[test]
+fn serves_and_refuses_accesses() {
+    // loads V0 from the device twice, then tries to overwrite the ROM
+    let program = [0xA0, 0x00, 0xF0, 0x65, 0xF0, 0x65, 0xA2, 0x00, 0xF0, 0x55];
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&program).unwrap();
+    chip8.i = 0;
+    chip8.set_bus(Some(Box::new(Device { counter: 0, rom_end: 0x200 + program.len() })));
+    // memory ends at 0xFFF, so ANNN can't point at the device and I is set past it by hand
+    chip8.single_cycle().unwrap();
+    chip8.i = 0x1000;
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.vregisters[0], 1);
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.vregisters[0], 2);
+
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.single_cycle(), Err(CpuFault::MemoryOutOfBounds { pc: 0x208, addr: 0x200 }));
+    assert_eq!(chip8.memory[0x200], 0xA0);
+
+    // without the bus the device is gone
+    chip8.set_bus(None);
+    chip8.i = 0x1000;
+    chip8.pc = 0x202;
+    assert_eq!(chip8.single_cycle(), Err(CpuFault::MemoryOutOfBounds { pc: 0x202, addr: 0x1000 }));
+}
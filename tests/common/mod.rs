@@ -0,0 +1,40 @@
+// Shared by the tests that restore earlier states: what they compare of a state, and a test ROM to run.
+
+use chip_8_emu::chip8::Chip8;
+
+#[derive(Debug, PartialEq)]
+pub struct State {
+    pc: u16,
+    i: u16,
+    vregisters: [u8; 16],
+    jumpstack: [u16; 16],
+    stackpointer: u16,
+    timers: (u8, u8),
+    hires: bool,
+    rng: u64,
+    memory: Vec<u8>,
+    display: Vec<u8>,
+}
+
+pub fn state(chip8: &Chip8) -> State {
+    State {
+        pc: chip8.pc,
+        i: chip8.i,
+        vregisters: chip8.vregisters,
+        jumpstack: chip8.jumpstack,
+        stackpointer: chip8.stackpointer,
+        timers: (chip8.delay_timer, chip8.sound_timer),
+        hires: chip8.hires,
+        rng: chip8.rng.state,
+        memory: chip8.memory.clone(),
+        display: chip8.display.clone(),
+    }
+}
+
+// a chip8 with a seeded rng and the ROM from tests/roms loaded
+pub fn load(rom: &str) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.seed_rng(0);
+    chip8.load_program(&std::fs::read(format!("{}/tests/roms/{}", env!("CARGO_MANIFEST_DIR"), rom)).unwrap()).unwrap();
+    chip8
+}
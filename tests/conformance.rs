@@ -0,0 +1,166 @@
+// Runs the test ROMs in tests/roms without a window and compares the display against the snapshots in tests/golden.
+// Run with UPDATE_GOLDEN=1 to rewrite the snapshots after an intended change, and check the diff.
+// The snapshots come from this emulator, so what the ROMs report is also read off the screen and checked on its own:
+// every check of the opcodes and flags ROMs has to pass, and the quirks ROM has to find the quirks of the preset.
+
+use std::path::PathBuf;
+
+use chip_8_emu::chip8::{Chip8, Mode};
+use chip_8_emu::dump;
+use chip_8_emu::fstools;
+use chip_8_emu::quirks::{Quirks, PRESET_NAMES};
+
+// enough for every ROM to finish, even with the display wait quirk limiting draws to one per frame
+const FRAMES: u32 = 120;
+const IPF: u32 = 15;
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file].iter().collect()
+}
+
+fn load(rom: &str, quirks: Quirks) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.set_mode(Mode::Chip8);
+    chip8.quirks = quirks;
+    chip8.seed_rng(0);
+    chip8.load_program(&std::fs::read(path("roms", rom)).unwrap()).unwrap();
+    chip8
+}
+
+fn run(chip8: &mut Chip8, frames: u32) {
+    for _ in 0..frames {
+        chip8.run_frame(IPF).unwrap();
+    }
+}
+
+// the tick the opcodes and flags ROMs draw for a passing check, and the font's 0 and 1 the quirks ROM draws
+static TICK: [u8; 5] = [0x01, 0x02, 0x84, 0x48, 0x30];
+static ZERO: [u8; 5] = [0xF0, 0x90, 0x90, 0x90, 0xF0];
+static ONE: [u8; 5] = [0x20, 0x60, 0x20, 0x20, 0x70];
+
+// the 8x5 pixels at x, y as sprite rows
+fn glyph(chip8: &Chip8, x: usize, y: usize) -> [u8; 5] {
+    let height = chip8.display_height();
+    let mut rows = [0; 5];
+    for (row, bits) in rows.iter_mut().enumerate() {
+        for col in 0..8 {
+            if chip8.display[(x + col) * height + y + row] != 0 {
+                *bits |= 0x80 >> col;
+            }
+        }
+    }
+    rows
+}
+
+// which of the first count checks passed, the results being drawn 8 per row
+fn checks(chip8: &Chip8, count: usize) -> Vec<bool> {
+    (0..count).map(|check| glyph(chip8, check % 8 * 8, check / 8 * 6) == TICK).collect()
+}
+
+fn assert_golden(chip8: &Chip8, name: &str) {
+    let golden = path("golden", &format!("{}.txt", name));
+    let actual = dump::ascii(chip8);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&golden)
+        .unwrap_or_else(|err| panic!("unable to read {}: {}, run with UPDATE_GOLDEN=1 to create it", golden.display(), err));
+    assert!(actual == expected, "display doesn't match {}\nexpected:\n{}\nactual:\n{}", golden.display(), expected, actual);
+}
+
+#[test]
+fn logo() {
+    let mut chip8 = load("logo.ch8", Quirks::default());
+    run(&mut chip8, FRAMES);
+    assert_golden(&chip8, "logo");
+}
+
+#[test]
+fn opcodes() {
+    // doesn't depend on quirks, so every preset shows the same screen
+    for preset in PRESET_NAMES {
+        let mut chip8 = load("opcodes.ch8", Quirks::from_preset(preset).unwrap());
+        run(&mut chip8, FRAMES);
+        assert_eq!(checks(&chip8, 24), [true; 24], "{}", preset);
+        assert_golden(&chip8, "opcodes");
+    }
+}
+
+#[test]
+fn flags() {
+    for preset in PRESET_NAMES {
+        let mut chip8 = load("flags.ch8", Quirks::from_preset(preset).unwrap());
+        run(&mut chip8, FRAMES);
+        assert_eq!(checks(&chip8, 16), [true; 16], "{}", preset);
+        assert_golden(&chip8, "flags");
+    }
+}
+
+#[test]
+fn quirks() {
+    for preset in PRESET_NAMES {
+        let quirks = Quirks::from_preset(preset).unwrap();
+        let mut chip8 = load("quirks.ch8", quirks);
+        run(&mut chip8, FRAMES);
+        let expected = [quirks.vf_reset, quirks.memory_increment, quirks.shifting, quirks.jumping, quirks.clipping, quirks.display_wait];
+        // the digits are 6 pixels apart, so only the 4 pixels wide font glyph is compared
+        let found: Vec<bool> = (0..expected.len()).map(|quirk| match glyph(&chip8, quirk * 6, 0).map(|row| row & 0xF0) {
+            rows if rows == ONE => true,
+            rows if rows == ZERO => false,
+            rows => panic!("quirk {} of {} drew {:02X?}", quirk, preset, rows),
+        }).collect();
+        assert_eq!(found, expected, "{}", preset);
+        assert_golden(&chip8, &format!("quirks_{}", preset));
+    }
+}
+
+#[test]
+fn keypad() {
+    let mut chip8 = load("keypad.ch8", Quirks::default());
+    run(&mut chip8, 5);
+
+    // FX0A doesn't return while the key is still held down
+    chip8.keystate[0xA] = 1;
+    run(&mut chip8, 5);
+    assert_golden(&chip8, "keypad_held");
+
+    chip8.keystate[0xA] = 0;
+    run(&mut chip8, 5);
+    assert_golden(&chip8, "keypad_released");
+
+    // EX9E and EXA1 see key 7 go down and come back up
+    chip8.keystate[0x7] = 1;
+    run(&mut chip8, 5);
+    chip8.keystate[0x7] = 0;
+    run(&mut chip8, 5);
+    assert_golden(&chip8, "keypad");
+}
+
+#[test]
+fn inverted_colors() {
+    // switching to hi-res and back fills the new display with the inverted background
+    let mut chip8 = Chip8::new();
+    chip8.set_mode(Mode::SuperChip);
+    chip8.blank_pixel = 1;
+    chip8.load_program(&[0x00, 0xFF, 0x00, 0xFE]).unwrap();
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.display.len(), 128 * 64);
+    assert!(chip8.display.iter().all(|&pixel| pixel == 1));
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.display.len(), 64 * 32);
+    assert!(chip8.display.iter().all(|&pixel| pixel == 1));
+}
+
+#[test]
+fn rom_errors() {
+    // a ROM has to fit after 0x200, XO-CHIP having room for a larger one
+    let mut chip8 = Chip8::new();
+    let err = chip8.load_program(&[0; 0xE01]).unwrap_err();
+    assert_eq!(err.to_string(), "the ROM is 3585 bytes, only 3584 fit in memory after 0x200");
+    chip8.set_mode(Mode::XoChip);
+    chip8.load_program(&[0; 0xE01]).unwrap();
+
+    let missing = path("roms", "missing.ch8");
+    assert!(fstools::load_rom_or_state(missing.to_str().unwrap(), &mut chip8).is_err());
+}
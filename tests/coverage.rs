@@ -0,0 +1,48 @@
+// Runs short programs with coverage and checks the flags of the addresses they used and the map.
+
+use chip_8_emu::chip8::{Chip8, Mode};
+use chip_8_emu::coverage::{Coverage, EXECUTED, READ, WRITTEN};
+
+fn run(mode: Mode, program: &[u8], cycles: u32) -> Coverage {
+    let mut chip8 = Chip8::new();
+    chip8.set_mode(mode);
+    chip8.load_program(program).unwrap();
+    chip8.coverage = Some(Coverage::new(program.len()));
+    for _ in 0..cycles {
+        chip8.single_cycle().unwrap();
+    }
+    chip8.coverage.take().unwrap()
+}
+
+#[test]
+fn map() {
+    // draws the sprite at 0x20E, writes the BCD of V0 over the first instructions, draws the font's 0 and loops
+    let program = [0xA2, 0x0E, 0xD0, 0x01, 0xA2, 0x00, 0xF0, 0x33, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x0C, 0xF0, 0x00];
+    let coverage = run(Mode::Chip8, &program, 8);
+    assert_eq!(coverage.flags(0x200), EXECUTED | WRITTEN);
+    assert_eq!(coverage.flags(0x20E), READ);
+    assert_eq!(coverage.flags(0x20F), 0);
+
+    let map = coverage.map();
+    let mut lines = map.lines();
+    assert_eq!(lines.next(), Some("# ROM 0x200-0x20F: 15 of 16 bytes used (93.8%), 14 executed, 1 read, 3 written"));
+    assert_eq!(lines.next(), Some("# executed and written, self-modifying: 0x200-0x202"));
+    assert_eq!(lines.next(), Some("# address flags: x executed, r read, w written"));
+    // the font is outside of the ROM, and only listed as it was read
+    let listed: Vec<&str> = lines.collect();
+    assert_eq!(listed[..6], ["0000 -r-", "0001 -r-", "0002 -r-", "0003 -r-", "0004 -r-", "0200 x-w"]);
+    assert_eq!(listed[listed.len() - 3..], ["020D x--", "020E -r-", "020F ---"]);
+    assert_eq!(listed.len(), 5 + 16);
+}
+
+#[test]
+fn xo_chip() {
+    // with both planes selected a sprite is read for each, then 5XY2 saves and FX65 loads 2 registers at 0x300
+    let program = [0xF3, 0x01, 0xA3, 0x00, 0xD0, 0x01, 0x50, 0x12, 0xF1, 0x65, 0xF0, 0x00, 0x03, 0x10, 0xF0, 0x02];
+    let coverage = run(Mode::XoChip, &program, 7);
+    assert_eq!([coverage.flags(0x300), coverage.flags(0x301), coverage.flags(0x302)], [READ | WRITTEN, READ | WRITTEN, 0]);
+    // the long load's address is part of the instruction, and F002 reads the 16 byte pattern at 0x310
+    assert_eq!(coverage.flags(0x20C), EXECUTED);
+    assert!((0x310..0x320).all(|addr| coverage.flags(addr) == READ));
+    assert_eq!(coverage.flags(0x320), 0);
+}
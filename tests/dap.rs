@@ -0,0 +1,232 @@
+// Talks to the debug adapter over loopback with a scripted client, and feeds it malformed messages and JSON.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chip_8_emu::dap;
+use chip_8_emu::json::Json;
+
+// V0 = 5, then calls a subroutine that does V0 += 1 forever
+static PROGRAM: [u8; 12] = [0x60, 0x05, 0x22, 0x08, 0x12, 0x02, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    seq: i64,
+    // events that arrived while waiting for a response
+    events: VecDeque<Json>,
+}
+
+impl Client {
+    fn read_message(&mut self) -> Json {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            match line.trim().strip_prefix("Content-Length:") {
+                Some(value) => length = value.trim().parse().unwrap(),
+                None if line.trim().is_empty() => break,
+                None => panic!("unexpected header {}", line),
+            }
+        }
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body).unwrap();
+        Json::parse(std::str::from_utf8(&body).unwrap()).unwrap()
+    }
+
+    // sends a request and returns its response
+    fn request(&mut self, command: &str, arguments: Json) -> Json {
+        self.seq += 1;
+        let body = Json::object([("seq", self.seq.into()), ("type", "request".into()), ("command", command.into()), ("arguments", arguments)]).to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        loop {
+            let message = self.read_message();
+            if message.get("type").as_str() == Some("event") {
+                self.events.push_back(message);
+                continue;
+            }
+            assert_eq!(message.get("request_seq").as_i64(), Some(self.seq));
+            assert_eq!(message.get("command").as_str(), Some(command));
+            return message;
+        }
+    }
+
+    // waits for the next event, which has to be the given one
+    fn event(&mut self, event: &str) -> Json {
+        let message = match self.events.pop_front() {
+            Some(message) => message,
+            None => self.read_message(),
+        };
+        assert_eq!(message.get("event").as_str(), Some(event), "unexpected {}", message);
+        message.get("body").clone()
+    }
+
+    fn stopped(&mut self) -> String {
+        self.event("stopped").get("reason").as_str().unwrap().to_string()
+    }
+
+    // the instruction pointers of the stack frames, innermost first
+    fn stack(&mut self) -> Vec<String> {
+        let response = self.request("stackTrace", Json::object([("threadId", 1.into())]));
+        response.get("body").get("stackFrames").as_array().unwrap().iter()
+            .map(|frame| frame.get("instructionPointerReference").as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+fn reference(addr: &str, offset: i64) -> Json {
+    Json::object([("instructionReference", addr.into()), ("offset", offset.into())])
+}
+
+fn memory(reference: &str, offset: i64, count: i64) -> Json {
+    Json::object([("memoryReference", reference.into()), ("offset", offset.into()), ("count", count.into())])
+}
+
+#[test]
+fn loopback_session() {
+    let rom = std::env::temp_dir().join(format!("chip-8-emu-dap-{}.ch8", std::process::id()));
+    std::fs::write(&rom, PROGRAM).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let adapter = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        dap::serve(BufReader::new(stream.try_clone().unwrap()), Box::new(stream)).unwrap();
+    });
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut client = Client { reader: BufReader::new(stream.try_clone().unwrap()), writer: stream, seq: 0, events: VecDeque::new() };
+
+    let response = client.request("initialize", Json::object([("adapterID", "chip-8".into())]));
+    assert_eq!(response.get("success"), &Json::Bool(true));
+    assert_eq!(response.get("body").get("supportsReadMemoryRequest"), &Json::Bool(true));
+    client.event("initialized");
+
+    let launch = Json::object([("program", rom.to_str().unwrap().into()), ("stopOnEntry", true.into())]);
+    assert_eq!(client.request("launch", launch.clone()).get("success"), &Json::Bool(true));
+    // a session debugs a single ROM
+    let response = client.request("launch", launch);
+    assert_eq!(response.get("success"), &Json::Bool(false));
+    assert_eq!(response.get("message").as_str(), Some("A ROM is already launched in this session"));
+
+    // references with an offset past the 16-bit address space aren't verified
+    let breakpoints = vec![reference("0x200", 8), reference("0x7FFFFFFFFFFFFFFF", 1), reference("0x200", -0x201)];
+    let response = client.request("setInstructionBreakpoints", Json::object([("breakpoints", breakpoints.into())]));
+    let verified: Vec<&Json> = response.get("body").get("breakpoints").as_array().unwrap().iter().map(|x| x.get("verified")).collect();
+    assert_eq!(verified, [&Json::Bool(true), &Json::Bool(false), &Json::Bool(false)]);
+
+    client.request("configurationDone", Json::Null);
+    assert_eq!(client.stopped(), "entry");
+    assert_eq!(client.stack(), ["0x0200"]);
+
+    // stepping, and continuing to the breakpoint in the subroutine
+    client.request("stepIn", Json::Null);
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.stack(), ["0x0202"]);
+    client.request("continue", Json::Null);
+    assert_eq!(client.stopped(), "breakpoint");
+    assert_eq!(client.stack(), ["0x0208", "0x0202"]);
+    client.request("stepOut", Json::Null);
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.stack(), ["0x0204"]);
+
+    // memory, with the bytes past the end of it unreadable
+    let body = client.request("readMemory", memory("0x200", 0, 4)).get("body").clone();
+    assert_eq!((body.get("address").as_str(), body.get("data").as_str()), (Some("0x0200"), Some("YAUiCA==")));
+    let body = client.request("readMemory", memory("0x200", 0xDFE, 4)).get("body").clone();
+    assert_eq!((body.get("data").as_str(), body.get("unreadableBytes").as_i64()), (Some("AAA="), Some(2)));
+    // offsets and counts that overflow are refused
+    let response = client.request("readMemory", memory("0x7FFFFFFFFFFFFFFF", 1, 4));
+    assert_eq!(response.get("message").as_str(), Some("Invalid memory offset"));
+    let response = client.request("readMemory", memory("0x200", 0, i64::MAX));
+    assert_eq!(response.get("message").as_str(), Some("Invalid memory count"));
+
+    client.request("disconnect", Json::Null);
+    adapter.join().unwrap();
+    std::fs::remove_file(&rom).unwrap();
+}
+
+// collects what the adapter writes
+#[derive(Clone, Default)]
+struct Written(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Written {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// serves the given input to the end, returning the error it stopped with and what it wrote
+fn serve(input: &str) -> (Option<std::io::ErrorKind>, String) {
+    let written = Written::default();
+    let result = dap::serve(Cursor::new(input.as_bytes().to_vec()), Box::new(written.clone()));
+    let output = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+    (result.err().map(|err| err.kind()), output)
+}
+
+fn message(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[test]
+fn malformed_messages() {
+    // unknown requests and bad arguments fail the request but keep the session going, other messages are ignored
+    let input = [
+        message(r#"{"seq":1,"type":"request","command":"frobnicate"}"#),
+        message(r#"{"seq":2,"type":"event","event":"output"}"#),
+        message(r#"{"seq":3,"type":"request","command":"launch","arguments":{"mode":"chip8"}}"#),
+    ].concat();
+    let (error, output) = serve(&input);
+    assert_eq!(error, None);
+    assert!(output.contains(r#""request_seq":1,"command":"frobnicate","success":false,"message":"Unsupported request 'frobnicate'""#));
+    assert!(!output.contains(r#""request_seq":2"#));
+    assert!(output.contains(r#""success":false,"message":"Missing 'program' in the launch arguments""#));
+
+    // bodies that aren't JSON and streams that end early end the session with an error
+    assert_eq!(serve(&message("{\"seq\":1,")).0, Some(std::io::ErrorKind::InvalidData));
+    assert_eq!(serve("Content-Length: 1000000000000\r\n\r\n{}").0, Some(std::io::ErrorKind::UnexpectedEof));
+    assert_eq!(serve("Content-Length: 10\r\n\r\n").0, Some(std::io::ErrorKind::UnexpectedEof));
+}
+
+#[test]
+fn json_round_trip() {
+    let text = r#"{"a":[1,-2.5,true,false,null,{}],"b":"quote \" backslash \\ lines \r\n tab \t control \u0001 é","":[[]]}"#;
+    let value = Json::parse(text).unwrap();
+    assert_eq!(value.to_string(), text);
+    assert_eq!(value.get("a").as_array().unwrap()[1], Json::Number(-2.5));
+    assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+
+    // whitespace goes, escapes are resolved and surrogate pairs combined
+    let value = Json::parse(" { \"s\" : \"\\/\\u00e9\\ud83d\\ude00\" , \"n\" : 1e3 } ").unwrap();
+    assert_eq!(value.get("s").as_str(), Some("/é😀"));
+    assert_eq!(value.get("n").as_i64(), Some(1000));
+    assert_eq!(value.to_string(), r#"{"s":"/é😀","n":1000}"#);
+}
+
+#[test]
+fn malformed_json() {
+    let error = |text: &str| Json::parse(text).unwrap_err();
+    assert_eq!(error(""), "unexpected character at 0");
+    assert_eq!(error("{"), "expected '\"' at 1");
+    assert_eq!(error("[1,]"), "unexpected character at 3");
+    assert_eq!(error(r#"{"a" 1}"#), "expected ':' at 5");
+    assert_eq!(error(r#"{"a":1"#), "expected ',' or '}' at 6");
+    assert_eq!(error(r#""abc"#), "unterminated string");
+    assert_eq!(error(r#""\x""#), "invalid escape at 2");
+    assert_eq!(error(r#""\u12""#), "truncated unicode escape");
+    assert_eq!(error(r#""\uzzzz""#), "invalid unicode escape");
+    assert_eq!(error("-"), "invalid number at 0");
+    assert_eq!(error("tru"), "expected 'true' at 0");
+    assert_eq!(error("1 2"), "unexpected trailing characters at 2");
+
+    // deep nesting is refused instead of overflowing the stack
+    assert!(Json::parse(&format!("{}{}", "[".repeat(100), "]".repeat(100))).is_ok());
+    assert_eq!(error(&"[".repeat(100_000)), "nested too deeply at 128");
+}
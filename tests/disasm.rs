@@ -0,0 +1,91 @@
+// Disassembles short ROMs and checks the listings: what is code and what is data, and the labels it names.
+
+use chip_8_emu::chip8::Mode;
+use chip_8_emu::disasm::disassemble;
+
+fn listing(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[test]
+fn code_and_data() {
+    // the bytes after the endless loop are never reached, the subroutine is called and the sprite loaded into I
+    let rom = [0xA2, 0x0A, 0x22, 0x08, 0x12, 0x04, 0x00, 0x00, 0x00, 0xEE, 0xF0, 0x90];
+    assert_eq!(disassemble(&rom, Mode::Chip8), listing(&[
+        "  0x200: A20A      LD I, data_20A",
+        "  0x202: 2208      CALL sub_208",
+        "label_204:",
+        "  0x204: 1204      JP label_204",
+        "  0x206: 00        DB 0x00  ........",
+        "  0x207: 00        DB 0x00  ........",
+        "sub_208:",
+        "  0x208: 00EE      RET",
+        "data_20A:",
+        "  0x20A: F0        DB 0xF0  ####....",
+        "  0x20B: 90        DB 0x90  #..#....",
+    ]));
+}
+
+#[test]
+fn skips() {
+    // skipping the XO-CHIP long load skips all 4 bytes of it, so its address is never decoded
+    let rom = [0x30, 0x00, 0xF0, 0x00, 0x02, 0x08, 0x12, 0x06, 0xFF];
+    assert_eq!(disassemble(&rom, Mode::XoChip), listing(&[
+        "  0x200: 3000      SE V0, 0x00",
+        "  0x202: F0000208  LD I, LONG data_208",
+        "label_206:",
+        "  0x206: 1206      JP label_206",
+        "data_208:",
+        "  0x208: FF        DB 0xFF  ########",
+    ]));
+
+    // outside of XO-CHIP the long load is data, and the skip lands on its address
+    let listing = disassemble(&rom, Mode::Chip8);
+    assert!(listing.contains("  0x202: F0        DB 0xF0  ####....\n"));
+    assert!(listing.contains("  0x204: 0208      SYS 0x208\n"));
+}
+
+#[test]
+fn jump_tables() {
+    // the table at 0x204 is followed for as long as it holds jumps
+    let rom = [0xB2, 0x04, 0x00, 0x00, 0x12, 0x08, 0x12, 0x0A, 0x00, 0xE0, 0x12, 0x0A, 0xFF];
+    assert_eq!(disassemble(&rom, Mode::Chip8), listing(&[
+        "  0x200: B204      JP V0, label_204",
+        "  0x202: 00        DB 0x00  ........",
+        "  0x203: 00        DB 0x00  ........",
+        "label_204:",
+        "  0x204: 1208      JP label_208",
+        "  0x206: 120A      JP label_20A",
+        "label_208:",
+        "  0x208: 00E0      CLS",
+        "label_20A:",
+        "  0x20A: 120A      JP label_20A",
+        "  0x20C: FF        DB 0xFF  ########",
+    ]));
+}
+
+#[test]
+fn labels_inside_instructions() {
+    // a jump into the operand of an instruction, which runs as code of its own, and I pointing at an operand
+    let rom = [0x60, 0x12, 0xA2, 0x05, 0x12, 0x01];
+    assert_eq!(disassemble(&rom, Mode::Chip8), listing(&[
+        "  0x200: 6012      LD V0, 0x12",
+        "label_201:  (the second byte of 0x200, also runs as JP 0x2A2)",
+        "  0x202: A205      LD I, data_205",
+        "  0x204: 1201      JP label_201",
+        "data_205:  (the second byte of 0x204)",
+    ]));
+}
+
+#[test]
+fn label_precedence() {
+    // a subroutine that is also jumped to and loaded into I is named as a subroutine
+    let rom = [0x22, 0x06, 0xA2, 0x06, 0x12, 0x06, 0x00, 0xEE];
+    assert_eq!(disassemble(&rom, Mode::Chip8), listing(&[
+        "  0x200: 2206      CALL sub_206",
+        "  0x202: A206      LD I, sub_206",
+        "  0x204: 1206      JP sub_206",
+        "sub_206:",
+        "  0x206: 00EE      RET",
+    ]));
+}
@@ -0,0 +1,138 @@
+// Talks to the GDB remote protocol stub over loopback with a scripted client.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::debugger::Debugger;
+use chip_8_emu::gdbstub::GdbStub;
+
+// V0 = 5, then V0 += 1 forever
+static PROGRAM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn write_packet(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", data, checksum).as_bytes()).unwrap();
+        assert_eq!(self.read_byte(), b'+', "packet {} wasn't acknowledged", data);
+    }
+
+    fn read_reply(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        let checksum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", expected));
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.write_packet(data);
+        self.read_reply()
+    }
+}
+
+#[test]
+fn loopback_session() {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&PROGRAM).unwrap();
+    let chip8 = Arc::new(RwLock::new(chip8));
+    let debugger = Arc::new(Mutex::new(Debugger::new(8)));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let stubchip8 = chip8.clone();
+    let stubdebugger = debugger.clone();
+    let stub = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(stream, stubchip8, stubdebugger).serve().unwrap();
+    });
+
+    // stands in for the frontend, running frames whenever the debugger isn't paused
+    let loopchip8 = chip8.clone();
+    let loopdebugger = debugger.clone();
+    let emulation = thread::spawn(move || loop {
+        let paused = {
+            let mut chip8 = loopchip8.write().unwrap();
+            let mut debugger = loopdebugger.lock().unwrap();
+            if debugger.quit {
+                return;
+            }
+            let _ = debugger.run_frame(&mut chip8);
+            debugger.paused
+        };
+        if paused {
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+    client.stream.set_nodelay(true).unwrap();
+    assert!(client.request("qSupported:swbreak+").contains("PacketSize="));
+    assert!(client.request("qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+    assert_eq!(client.request("?"), "S05");
+    // empty packets and ones that start with a multibyte character are unsupported, and the stub keeps serving
+    assert_eq!(client.request(""), "");
+    assert_eq!(client.request("é1"), "");
+    assert!(client.request("qXfer:features:read:target.xml:1,ffffffffffffffff").starts_with("l?xml"));
+
+    // registers: V0-VF, I, PC, SP, DT, ST
+    assert_eq!(client.request("g"), format!("{}{}{}{}", "00".repeat(16), "0000", "0200", "000000"));
+    assert_eq!(client.request("m200,6"), "600570011202");
+
+    // single step
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p11"), "0202");
+    assert_eq!(client.request("p0"), "05");
+
+    // continue to a breakpoint, and again from it
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "0204");
+    assert_eq!(client.request("p0"), "06");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p0"), "07");
+    assert_eq!(client.request("z0,204,2"), "OK");
+
+    // editing registers and memory
+    assert_eq!(client.request("P0=ff"), "OK");
+    assert_eq!(client.request("p0"), "ff");
+    assert_eq!(client.request("M300,2:abcd"), "OK");
+    assert_eq!(client.request("m300,2"), "abcd");
+    assert_eq!(chip8.read().unwrap().memory[0x300..0x302], [0xAB, 0xCD]);
+    // writes past the end of memory, or whose end overflows, are refused
+    assert_eq!(client.request("MFFF,2:abcd"), "E01");
+    assert_eq!(client.request("Mffffffffffffffff,1:ab"), "E01");
+    assert_eq!(client.request("M300,3:abcd"), "E01");
+
+    // interrupt a running program
+    client.write_packet("c");
+    thread::sleep(Duration::from_millis(20));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.read_reply(), "S02");
+
+    // kill ends the session and the frontend
+    client.stream.write_all(b"$k#6b").unwrap();
+    stub.join().unwrap();
+    emulation.join().unwrap();
+}
@@ -0,0 +1,50 @@
+// Runs the headless runner on small ROMs and checks its exit codes: 1 for a cpu fault, 2 for bad arguments or files.
+
+#![cfg(feature = "cli")]
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// writes the ROM to a file of its own for the runner to load
+fn rom(name: &str, program: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("chip-8-emu-headless-{}-{}.ch8", name, std::process::id()));
+    std::fs::write(&path, program).unwrap();
+    path
+}
+
+// the exit code of the runner with the given arguments after the ROM, and nothing on stdin
+fn run(rom: &PathBuf, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_chip-8-headless"))
+        .arg(rom)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .code()
+}
+
+#[test]
+fn faults() {
+    // returns with an empty stack, with and without watchpoints that never pause
+    let path = rom("faults", &[0x00, 0xEE]);
+    assert_eq!(run(&path, &[]), Some(1));
+    assert_eq!(run(&path, &["--watch", "v3"]), Some(1));
+    assert_eq!(run(&path, &["--break-when", "v3 == 1"]), Some(1));
+    std::fs::remove_file(&path).unwrap();
+
+    let path = rom("loops", &[0x12, 0x00]);
+    assert_eq!(run(&path, &["--watch", "v3"]), Some(0));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bad_arguments() {
+    let path = rom("arguments", &[0x12, 0x00]);
+    assert_eq!(run(&path, &["--watch", "peek 0x300"]), Some(2));
+    assert_eq!(run(&path, &["--break-when", "v3 >"]), Some(2));
+    assert_eq!(run(&path, &["--trace-pc", "0x200"]), Some(2));
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(run(&path, &[]), Some(2));
+}
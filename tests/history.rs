@@ -0,0 +1,82 @@
+// Runs test ROMs with an instruction history, then steps back through it and checks every state it restores.
+
+use chip_8_emu::chip8::{Chip8, Mode};
+use chip_8_emu::history::History;
+
+mod common;
+
+use common::state;
+
+// the ROM with an instruction history of budget bytes
+fn load(rom: &str, budget: usize) -> Chip8 {
+    let mut chip8 = common::load(rom);
+    chip8.history = Some(History::new(budget));
+    chip8
+}
+
+#[test]
+fn undoes_every_instruction() {
+    // the opcodes ROM draws, calls, stores, writes BCD and reads the timers
+    let mut chip8 = load("opcodes.ch8", 10_000);
+    let mut states = vec![state(&chip8)];
+    for frame in 0..40 {
+        for _ in 0..15 {
+            chip8.single_cycle().unwrap();
+            states.push(state(&chip8));
+        }
+        // timer ticks are undone along with the instruction after them
+        chip8.tick_timers();
+        if frame < 39 {
+            states.pop();
+            states.push(state(&chip8));
+        }
+    }
+    states.pop();
+
+    for expected in states.iter().rev() {
+        assert_eq!(chip8.step_back(1), 1);
+        assert_eq!(&state(&chip8), expected);
+    }
+    assert_eq!(chip8.step_back(1), 0);
+}
+
+#[test]
+fn budget_and_conditions() {
+    let mut chip8 = load("quirks.ch8", 50);
+    for _ in 0..200 {
+        chip8.single_cycle().unwrap();
+    }
+    assert_eq!(chip8.history.as_ref().unwrap().len(), 50);
+    assert_eq!(chip8.step_back(100), 50);
+
+    // runs back to the instruction before the first sprite was drawn
+    let mut chip8 = load("quirks.ch8", 1000);
+    for _ in 0..200 {
+        chip8.single_cycle().unwrap();
+    }
+    assert!(chip8.run_back_until(|chip8| chip8.display.iter().all(|&pixel| pixel == 0)));
+    assert_eq!(chip8.memory[chip8.pc as usize] & 0xF0, 0xD0);
+    assert!(!chip8.run_back_until(|chip8| chip8.pc == 0x100));
+    assert!(chip8.history.as_ref().unwrap().is_empty());
+}
+
+#[test]
+fn undoes_faults_and_resolution_changes() {
+    // 00FF switches to hi-res, then FA55 with I near the end of memory faults after writing V0 and V1
+    let mut chip8 = Chip8::new();
+    chip8.set_mode(Mode::SuperChip);
+    chip8.load_program(&[0x00, 0xFF, 0x60, 0x07, 0x61, 0x08, 0xAF, 0xFE, 0xFA, 0x55]).unwrap();
+    chip8.history = Some(History::new(10));
+    let mut states = vec![state(&chip8)];
+    for _ in 0..4 {
+        chip8.single_cycle().unwrap();
+        states.push(state(&chip8));
+    }
+    assert!(chip8.single_cycle().is_err());
+    assert_eq!(chip8.memory[0xFFE..], [7, 8]);
+
+    for expected in states.iter().rev() {
+        assert_eq!(chip8.step_back(1), 1);
+        assert_eq!(&state(&chip8), expected);
+    }
+}
@@ -0,0 +1,84 @@
+// Runs short programs with an observer and checks the events it hears of, in order.
+
+use std::sync::{Arc, Mutex};
+
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::fault::CpuFault;
+use chip_8_emu::observer::Event;
+
+// a chip8 running program, and the events its observer heard of
+fn observed(program: &[u8]) -> (Chip8, Arc<Mutex<Vec<Event>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let heard = events.clone();
+    let mut chip8 = Chip8::new();
+    chip8.load_program(program).unwrap();
+    chip8.set_observer(Some(Box::new(move |_: &Chip8, event| heard.lock().unwrap().push(event))));
+    (chip8, events)
+}
+
+fn take(events: &Arc<Mutex<Vec<Event>>>) -> Vec<Event> {
+    std::mem::take(&mut events.lock().unwrap())
+}
+
+#[test]
+fn events() {
+    // clears the screen, calls a subroutine that starts the sound and draws, then waits for a key
+    let program = [0x00, 0xE0, 0x22, 0x08, 0xF0, 0x0A, 0x80, 0x08, 0x60, 0x02, 0xF0, 0x18, 0xD0, 0x01, 0x00, 0xEE];
+    let (mut chip8, events) = observed(&program);
+    for _ in 0..6 {
+        chip8.single_cycle().unwrap();
+    }
+    assert_eq!(take(&events), [
+        Event::Drawn,
+        Event::Call { pc: 0x202, addr: 0x208 },
+        Event::SoundStarted,
+        Event::Drawn,
+        Event::Return { pc: 0x20E, addr: 0x204 },
+    ]);
+
+    // the wait is only heard of once, however long it takes
+    for _ in 0..3 {
+        chip8.single_cycle().unwrap();
+    }
+    chip8.keystate[5] = 1;
+    chip8.single_cycle().unwrap();
+    chip8.keystate[5] = 0;
+    chip8.single_cycle().unwrap();
+    assert_eq!((chip8.pc, chip8.vregisters[0]), (0x206, 5));
+    assert_eq!(take(&events), [Event::KeyWait(0)]);
+
+    // the sound stops when the timer runs out
+    chip8.tick_timers();
+    assert!(take(&events).is_empty());
+    chip8.tick_timers();
+    assert_eq!(take(&events), [Event::SoundStopped]);
+
+    assert!(chip8.single_cycle().is_err());
+    assert_eq!(take(&events), [Event::UnknownOpcode { pc: 0x206, opcode: 0x8008 }]);
+}
+
+#[test]
+fn faults_and_quiet_instructions() {
+    // a scroll outside of SUPER-CHIP is ignored and draws nothing, and returning with an empty stack faults
+    let (mut chip8, events) = observed(&[0x00, 0xC4, 0x00, 0xEE]);
+    chip8.single_cycle().unwrap();
+    assert_eq!(chip8.single_cycle(), Err(CpuFault::StackUnderflow { pc: 0x202 }));
+    assert_eq!(take(&events), [Event::Fault(CpuFault::StackUnderflow { pc: 0x202 })]);
+
+    // without an observer nothing is heard of
+    chip8.set_observer(None);
+    chip8.pc = 0x200;
+    chip8.single_cycle().unwrap();
+    assert!(take(&events).is_empty());
+}
+
+#[test]
+fn restored_states() {
+    // restoring a state shows its display, and starts or stops the sound with its timer
+    let (mut chip8, events) = observed(&[0x00, 0xE0]);
+    let mut sounding = Chip8::new();
+    sounding.sound_timer = 5;
+    chip8.restore_from(sounding).unwrap();
+    chip8.restore_from(Chip8::new()).unwrap();
+    assert_eq!(take(&events), [Event::Drawn, Event::SoundStarted, Event::Drawn, Event::SoundStopped]);
+}
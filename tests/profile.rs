@@ -0,0 +1,54 @@
+// Profiles a short program with nested calls and checks the counts in the report and the folded stacks.
+
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::profile::Profile;
+
+// calls 0x20A twice and loops at 0x204, 0x20A calls 0x206 and both add to a register before returning
+static PROGRAM: [u8; 16] = [0x22, 0x0A, 0x22, 0x0A, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE, 0x22, 0x06, 0x71, 0x01, 0x00, 0xEE];
+
+fn run(cycles: u32) -> Profile {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&PROGRAM).unwrap();
+    chip8.profile = Some(Profile::new());
+    for _ in 0..cycles {
+        chip8.single_cycle().unwrap();
+    }
+    chip8.profile.take().unwrap()
+}
+
+// the fields of the first line of a section that starts with prefix
+fn fields<'a>(report: &'a str, section: &str, prefix: &str) -> Vec<&'a str> {
+    let start = report.find(section).unwrap();
+    let line = report[start..].lines().find(|line| line.starts_with(prefix)).unwrap_or_else(|| panic!("no '{}' in {}", prefix, report));
+    line.split_whitespace().collect()
+}
+
+#[test]
+fn report() {
+    let profile = run(14);
+    assert_eq!(profile.instructions(), 14);
+    let report = profile.report();
+    assert!(report.starts_with("14 instructions\n"), "{}", report);
+
+    // the loop at 0x204 ran twice, ties with it are listed by address
+    assert_eq!(fields(&report, "Top addresses:", "  0x"), ["0x204", "2", "14.29%", "1204", "JP", "0x204"]);
+    assert_eq!(fields(&report, "Instruction mix:", "  2NNN"), ["2NNN", "4", "28.57%"]);
+    assert_eq!(fields(&report, "Instruction mix:", "  1NNN"), ["1NNN", "2", "14.29%"]);
+
+    assert_eq!(fields(&report, "Call graph:", "  start"), ["start", "1", "14", "100.00%", "4", "28.57%"]);
+    assert_eq!(fields(&report, "Call graph:", "  sub_20A"), ["sub_20A", "2", "10", "71.43%", "6", "42.86%"]);
+    assert_eq!(fields(&report, "Call graph:", "  sub_206"), ["sub_206", "2", "4", "28.57%", "4", "28.57%"]);
+    assert_eq!(fields(&report, "  sub_20A", "    <- "), ["<-", "start", "2", "10"]);
+    assert_eq!(fields(&report, "  sub_20A", "    -> "), ["->", "sub_206", "2", "4"]);
+
+    assert_eq!(profile.folded(), "start 4\nstart;sub_20A 6\nstart;sub_20A;sub_206 4\n");
+}
+
+#[test]
+fn running_subroutines() {
+    // stopped inside the second call of 0x206, whose instructions so far still count towards every caller
+    let report = run(9).report();
+    assert_eq!(fields(&report, "Call graph:", "  start"), ["start", "1", "9", "100.00%", "2", "22.22%"]);
+    assert_eq!(fields(&report, "Call graph:", "  sub_20A"), ["sub_20A", "2", "7", "77.78%", "4", "44.44%"]);
+    assert_eq!(fields(&report, "Call graph:", "  sub_206"), ["sub_206", "2", "3", "33.33%", "3", "33.33%"]);
+}
@@ -0,0 +1,86 @@
+// Runs a test ROM while taking rewind snapshots or saving states, then restores them and checks the states they hold.
+
+use chip_8_emu::fstools;
+use chip_8_emu::rewind::{Rewind, KEYFRAME_INTERVAL};
+
+mod common;
+
+use common::{load, state};
+
+#[test]
+fn steps_back_through_frames() {
+    let depth = 100;
+    let mut chip8 = load("opcodes.ch8");
+    let mut rewind = Rewind::new(depth, 1);
+    let mut states = Vec::new();
+    for _ in 0..150 {
+        // a frame at a time, so that the ROM is still drawing when the history starts
+        chip8.run_frame(1).unwrap();
+        rewind.frame(&chip8);
+        states.push(state(&chip8));
+    }
+    // the oldest snapshots are dropped a keyframe group at a time
+    assert!(rewind.len() >= depth && rewind.len() < depth + KEYFRAME_INTERVAL, "{} snapshots", rewind.len());
+
+    chip8.keystate[5] = 1;
+    let kept = rewind.len();
+    for expected in states.iter().rev().take(kept) {
+        assert!(rewind.step_back(&mut chip8));
+        assert_eq!(&state(&chip8), expected);
+        assert_eq!(chip8.keystate[5], 1, "keys held now are kept");
+    }
+    assert!(rewind.is_empty());
+    assert!(!rewind.step_back(&mut chip8));
+}
+
+#[test]
+fn interval_and_resuming() {
+    let mut chip8 = load("opcodes.ch8");
+    let mut rewind = Rewind::new(10, 4);
+    for _ in 0..8 {
+        chip8.run_frame(1).unwrap();
+        rewind.frame(&chip8);
+    }
+    assert_eq!(rewind.len(), 2);
+
+    // stepping back and running again continues the same history from the restored state
+    let mut reference = load("opcodes.ch8");
+    for _ in 0..4 {
+        reference.run_frame(1).unwrap();
+    }
+    assert!(rewind.step_back(&mut chip8));
+    assert!(rewind.step_back(&mut chip8));
+    assert_eq!(state(&chip8), state(&reference));
+    chip8.run_frame(1).unwrap();
+    reference.run_frame(1).unwrap();
+    assert_eq!(state(&chip8), state(&reference));
+}
+
+#[test]
+fn save_states() {
+    let dir = std::env::temp_dir().join(format!("chip-8-emu-states-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("opcodes.state");
+    let mut chip8 = load("opcodes.ch8");
+    chip8.run_frame(20).unwrap();
+    fstools::save_state(&path, &chip8).unwrap();
+
+    let mut restored = load("opcodes.ch8");
+    fstools::load_rom_or_state(path.to_str().unwrap(), &mut restored).unwrap();
+    assert_eq!(state(&restored), state(&chip8));
+
+    // a state that can't be loaded is an error and leaves the machine as it was
+    std::fs::write(&path, b"not a state").unwrap();
+    let mut fresh = load("opcodes.ch8");
+    assert!(fstools::load_state(&path, &mut fresh).is_err());
+    assert_eq!(state(&fresh), state(&load("opcodes.ch8")));
+    assert_eq!(fstools::load_state(&dir.join("missing.state"), &mut fresh).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    // so is one whose display doesn't match its resolution
+    let mut mismatched = load("opcodes.ch8");
+    mismatched.hires = true;
+    fstools::save_state(&path, &mismatched).unwrap();
+    assert_eq!(fstools::load_state(&path, &mut fresh).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(state(&fresh), state(&load("opcodes.ch8")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,49 @@
+// Reads label files in the forms we accept, and checks addresses resolve to labels and back, in the debugger too.
+
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::debugger::Debugger;
+use chip_8_emu::fault::CpuFault;
+use chip_8_emu::symbols::SymbolMap;
+
+fn symbols() -> SymbolMap {
+    SymbolMap::parse("# labels\nmain 0x200\n0x21A draw\n: sprite 0x300\n").unwrap()
+}
+
+#[test]
+fn label_files() {
+    let symbols = symbols();
+    assert_eq!(symbols.labels["main"], 0x200);
+    assert_eq!(symbols.labels["draw"], 0x21A);
+    assert_eq!(symbols.labels["sprite"], 0x300);
+    assert!(SymbolMap::parse("main zero").is_err());
+}
+
+#[test]
+fn describe_and_resolve() {
+    let symbols = symbols();
+    assert_eq!(symbols.describe(0x21E), "draw+0x4");
+    assert_eq!(symbols.describe(0x21A), "draw");
+    assert_eq!(symbols.describe(0x050), "0x050");
+    assert_eq!(symbols.resolve("draw+0x4"), Some(0x21E));
+    assert_eq!(symbols.resolve("main+4"), Some(0x204));
+    assert_eq!(symbols.resolve("0x123"), Some(0x123));
+    assert_eq!(symbols.resolve("missing"), None);
+    for addr in [0x200, 0x21F, 0x345] {
+        assert_eq!(symbols.resolve(&symbols.describe(addr)), Some(addr));
+    }
+
+    let fault = CpuFault::StackUnderflow { pc: 0x21E };
+    assert_eq!(fault.describe(&symbols), "stack underflow at draw+0x4");
+    assert_eq!(fault.to_string(), "stack underflow at 0x21E");
+}
+
+#[test]
+fn debugger() {
+    let mut chip8 = Chip8::new();
+    chip8.symbols = symbols();
+    let mut debugger = Debugger::new(8);
+    assert_eq!(debugger.command(&mut chip8, "break draw+2"), "Breakpoint set at draw+0x2");
+    assert!(debugger.breakpoints.contains(&0x21C));
+    assert_eq!(debugger.command(&mut chip8, "delete 0x21C"), "Deleted breakpoint at draw+0x2");
+    assert!(debugger.command(&mut chip8, "list main+2 2").starts_with("main:\n>  0x200: "));
+}
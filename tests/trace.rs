@@ -0,0 +1,156 @@
+// Traces a short program and checks the filters and both forms of the trace, then diffs runs of the quirks ROM.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use chip_8_emu::bus::{Bus, FlatMemory};
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::quirks::Quirks;
+use chip_8_emu::tracediff;
+use chip_8_emu::trace::{Trace, TraceFilter, TraceFormat, TraceReader, TraceRecord, BINARY_MAGIC, RECORD_SIZE};
+
+// V0 = 5, then V0 += 1, I = 0x300 and the BCD of V0 to I forever
+static PROGRAM: [u8; 10] = [0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x02];
+
+// collects what the trace writes so that the test can read it back
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(format: TraceFormat, filter: TraceFilter, cycles: u32) -> Vec<u8> {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&PROGRAM).unwrap();
+    trace(chip8, format, filter, cycles)
+}
+
+fn trace(mut chip8: Chip8, format: TraceFormat, filter: TraceFilter, cycles: u32) -> Vec<u8> {
+    let buffer = Buffer::default();
+    chip8.trace = Some(Trace::new(Box::new(buffer.clone()), format, filter).unwrap());
+    for _ in 0..cycles {
+        chip8.single_cycle().unwrap();
+    }
+    chip8.trace.as_mut().unwrap().flush().unwrap();
+    let bytes = buffer.0.lock().unwrap().clone();
+    bytes
+}
+
+#[test]
+fn text() {
+    let text = String::from_utf8(run(TraceFormat::Text, TraceFilter::default(), 4)).unwrap();
+    assert_eq!(text, "\
+# cycle pc opcode v0-vf i sp write instruction
+0 0200 6005 05000000000000000000000000000000 0000 00 - LD V0, 0x05
+1 0202 7001 06000000000000000000000000000000 0000 00 - ADD V0, 0x01
+2 0204 A300 06000000000000000000000000000000 0300 00 - LD I, 0x300
+3 0206 F033 06000000000000000000000000000000 0300 00 0300=000006 LD B, V0
+");
+    let records: Vec<TraceRecord> = text.lines().filter_map(|line| TraceRecord::parse(line).unwrap()).collect();
+    assert_eq!(records[3].write, Some((0x300, vec![0, 0, 6])));
+}
+
+#[test]
+fn filters() {
+    let filter = TraceFilter { pcs: TraceFilter::parse_pcs("0x202-0x202"), cycles: TraceFilter::parse_cycles("4-").unwrap() };
+    let text = String::from_utf8(run(TraceFormat::Text, filter, 12)).unwrap();
+    let cycles: Vec<&str> = text.lines().skip(1).map(|line| line.split(' ').next().unwrap()).collect();
+    assert_eq!(cycles, ["5", "9"]);
+
+    assert_eq!(TraceFilter::parse_cycles("-500"), Some((0, 500)));
+    assert_eq!(TraceFilter::parse_pcs("0x300-0x200"), None);
+}
+
+#[test]
+fn binary() {
+    let bytes = run(TraceFormat::Binary, TraceFilter::default(), 5);
+    assert_eq!(&bytes[..4], BINARY_MAGIC);
+    // the BCD also writes its address and 3 bytes
+    assert_eq!(bytes.len(), 4 + 5 * RECORD_SIZE + 5);
+
+    let mut reader = &bytes[4..];
+    let mut records = Vec::new();
+    while let Some(record) = TraceRecord::read_binary(&mut reader).unwrap() {
+        records.push(record);
+    }
+    assert_eq!(records.len(), 5);
+    assert_eq!(records[3], TraceRecord { cycle: 3, pc: 0x206, opcode: 0xF033, vregisters: [6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], i: 0x300, sp: 0, write: Some((0x300, vec![0, 0, 6])) });
+    assert_eq!(records[4], TraceRecord { cycle: 4, pc: 0x208, opcode: 0x1202, vregisters: [6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], i: 0x300, sp: 0, write: None });
+}
+
+#[test]
+fn malformed_lines() {
+    let registers = "0".repeat(32);
+    let line = |registers: &str, write: &str| format!("3 0206 F033 {} 0300 00 {}", registers, write);
+    assert!(TraceRecord::parse(&line(&registers, "0300=000006")).unwrap().is_some());
+
+    // pairs of hex digits that split a multibyte character are refused instead of sliced
+    assert_eq!(TraceRecord::parse(&line(&registers, "0300=0é0")), Err("invalid write '0é0'".to_string()));
+    let split = format!("0é0{}", "0".repeat(28));
+    assert_eq!(TraceRecord::parse(&line(&split, "-")), Err(format!("invalid registers '{}'", split)));
+    assert_eq!(TraceRecord::parse(&line(&registers, "0300=00000")), Err("invalid write '00000'".to_string()));
+    assert_eq!(TraceRecord::parse(&line("00", "-")), Err("expected 16 registers, found 1".to_string()));
+    assert_eq!(TraceRecord::parse("3 0206 F033"), Err("expected '<cycle> <pc> <opcode> <v0-vf> <i> <sp> <write>'".to_string()));
+    assert_eq!(TraceRecord::parse(&line(&registers, "0300")), Err("invalid write '0300'".to_string()));
+}
+
+// takes the writes past the end of memory and drops them
+struct Sink;
+
+impl Bus for Sink {
+    fn write(&mut self, memory: &mut [u8], addr: usize, value: u8) -> bool {
+        addr >= memory.len() || FlatMemory.write(memory, addr, value)
+    }
+}
+
+#[test]
+fn writes_past_memory() {
+    // stores BCD at the last 2 bytes of memory and the byte past it, then entirely past it
+    let mut chip8 = Chip8::new();
+    chip8.load_program(&[0xAF, 0xFE, 0xF0, 0x33, 0xF0, 0x33]).unwrap();
+    chip8.set_bus(Some(Box::new(Sink)));
+    let buffer = Buffer::default();
+    chip8.trace = Some(Trace::new(Box::new(buffer.clone()), TraceFormat::Text, TraceFilter::default()).unwrap());
+    chip8.single_cycle().unwrap();
+    chip8.single_cycle().unwrap();
+    chip8.i = 0x1000;
+    chip8.single_cycle().unwrap();
+    chip8.trace.as_mut().unwrap().flush().unwrap();
+
+    let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let records: Vec<TraceRecord> = text.lines().filter_map(|line| TraceRecord::parse(line).unwrap()).collect();
+    assert_eq!(records[1].write, Some((0xFFE, vec![0, 0])));
+    assert_eq!(records[2].write, None);
+}
+
+fn quirks_rom(preset: &str, format: TraceFormat) -> Vec<u8> {
+    let mut chip8 = Chip8::new();
+    chip8.quirks = Quirks::from_preset(preset).unwrap();
+    chip8.load_program(&std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/quirks.ch8")).unwrap()).unwrap();
+    trace(chip8, format, TraceFilter::default(), 20)
+}
+
+#[test]
+fn diff() {
+    let modern = quirks_rom("modern", TraceFormat::Text);
+    let same = tracediff::diff(&mut TraceReader::new(&modern[..]).unwrap(), &mut TraceReader::new(&modern[..]).unwrap(), ["a", "b"], 2).unwrap();
+    assert_eq!(same, None);
+
+    // the vf reset quirk makes 8XY1 clear VF, the binary and text forms can be compared with each other
+    let vip = quirks_rom("vip", TraceFormat::Binary);
+    let report = tracediff::diff(&mut TraceReader::new(&modern[..]).unwrap(), &mut TraceReader::new(&vip[..]).unwrap(), ["modern", "vip"], 2)
+        .unwrap()
+        .unwrap();
+    assert!(report.starts_with("First divergence: cycle 6 differs:\n  VF: 05 vs 00\n"), "{}", report);
+    assert!(report.contains("\nBefore, in both:\n  4 0208 6001 "), "{}", report);
+    assert!(report.contains("\nmodern:\n> 6 020C 8011 "), "{}", report);
+    assert!(report.contains("\nvip:\n> 6 020C 8011 "), "{}", report);
+}
@@ -0,0 +1,153 @@
+// Evaluates conditions over the machine state, and runs short programs under the debugger until a conditional
+// breakpoint or a watchpoint pauses them.
+
+use chip_8_emu::chip8::Chip8;
+use chip_8_emu::condition::Condition;
+use chip_8_emu::debugger::Debugger;
+use chip_8_emu::symbols::SymbolMap;
+
+// counts V3 up at 0x200, stores its BCD at 0x300 every time it reaches 0x0C and loops
+static PROGRAM: [u8; 14] = [0x73, 0x01, 0x33, 0x0C, 0x12, 0x00, 0xA3, 0x00, 0xF3, 0x33, 0x63, 0x00, 0x12, 0x00];
+
+fn debugger(program: &[u8]) -> (Chip8, Debugger) {
+    let mut chip8 = Chip8::new();
+    chip8.load_program(program).unwrap();
+    let mut debugger = Debugger::new(8);
+    debugger.resume();
+    (chip8, debugger)
+}
+
+// runs frames until the debugger pauses
+fn run(chip8: &mut Chip8, debugger: &mut Debugger) {
+    for _ in 0..100 {
+        if debugger.run_frame(chip8).unwrap() {
+            return;
+        }
+    }
+    panic!("the debugger didn't pause");
+}
+
+#[test]
+fn conditions() {
+    let mut chip8 = Chip8::new();
+    chip8.pc = 0x2A0;
+    chip8.i = 0x310;
+    chip8.vregisters[3] = 11;
+    chip8.memory[0x311] = 0x80;
+    let symbols = SymbolMap::parse("sprite 0x310").unwrap();
+    let value = |text: &str| Condition::parse(text, &symbols).unwrap().value(&chip8);
+    assert_eq!(value("pc == 0x2A0 && v3 > 10"), 1);
+    assert_eq!(value("i in 0x300..0x320"), 1);
+    assert_eq!(value("i in 0x300..sprite"), 0);
+    assert_eq!(value("dt == 0 || V3 == 0"), 1);
+    assert_eq!(value("mem[sprite + 1] & 0x80"), 0x80);
+    assert_eq!(value("!(v3 - 11) && -1 < 0"), 1);
+    assert_eq!(value("(1 + 2 == 3) == 1"), 1);
+
+    assert_eq!(Condition::parse("v3 >", &symbols), Err("unexpected end of the condition".to_string()));
+    assert_eq!(Condition::parse("v3 > missing", &symbols), Err("unknown name 'missing'".to_string()));
+    assert_eq!(Condition::parse("v3 = 1", &symbols), Err("unexpected '='".to_string()));
+    assert_eq!(Condition::parse("(v3", &symbols), Err("expected ')'".to_string()));
+    assert_eq!(Condition::parse("v3 v4", &symbols), Err("unexpected 'v4'".to_string()));
+    assert_eq!(Condition::parse("v3 == 1 == 1", &symbols), Err("unexpected '=='".to_string()));
+}
+
+#[test]
+fn conditional_breakpoints() {
+    let (mut chip8, mut debugger) = debugger(&PROGRAM);
+    assert_eq!(debugger.command(&mut chip8, "break 0x202 if v3 == 5"), "Breakpoint set at 0x202 if v3 == 5");
+    run(&mut chip8, &mut debugger);
+    assert_eq!((chip8.pc, chip8.vregisters[3]), (0x202, 5));
+    assert_eq!(debugger.stop_message(&chip8), "Breakpoint at 0x202");
+
+    // the condition goes with the breakpoint
+    assert_eq!(debugger.command(&mut chip8, "break"), "0x202 if v3 == 5");
+    debugger.command(&mut chip8, "delete 0x202");
+    assert!(debugger.conditions.is_empty());
+    assert!(debugger.command(&mut chip8, "break 0x202 v3").starts_with("Usage"));
+}
+
+#[test]
+fn condition_watchpoints() {
+    let (mut chip8, mut debugger) = debugger(&PROGRAM);
+    assert_eq!(debugger.command(&mut chip8, "watch if v3 > 10"), "Watchpoint 1: if v3 > 10");
+    run(&mut chip8, &mut debugger);
+    assert_eq!(chip8.vregisters[3], 11);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (if v3 > 10): became true after 0x200");
+
+    // it doesn't pause again while the condition still holds, only once it became false and true again
+    debugger.resume();
+    run(&mut chip8, &mut debugger);
+    assert_eq!((chip8.vregisters[3], chip8.pc), (11, 0x202));
+    assert!(chip8.memory[0x300..0x303] == [0, 1, 2]);
+    assert_eq!(debugger.command(&mut chip8, "unwatch 1"), "Deleted watchpoint 1: if v3 > 10");
+    assert_eq!(debugger.command(&mut chip8, "watch"), "No watchpoints.");
+}
+
+#[test]
+fn memory_and_register_watchpoints() {
+    let (mut chip8, mut debugger) = debugger(&PROGRAM);
+    assert_eq!(debugger.command(&mut chip8, "watch read 0x300..0x310"), "Watchpoint 1: read 0x300..0x310");
+    assert_eq!(debugger.command(&mut chip8, "watch write 0x302"), "Watchpoint 2: write 0x302..0x303");
+    run(&mut chip8, &mut debugger);
+    assert_eq!(chip8.pc, 0x20A);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 2 (write 0x302..0x303): FX33 at 0x208 wrote 0x300..0x303");
+
+    debugger.command(&mut chip8, "unwatch all");
+    assert_eq!(debugger.command(&mut chip8, "w v3"), "Watchpoint 1: v3");
+    debugger.resume();
+    run(&mut chip8, &mut debugger);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (v3): 0C -> 00 after 0x20A");
+    debugger.resume();
+    run(&mut chip8, &mut debugger);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (v3): 00 -> 01 after 0x200");
+
+    assert_eq!(debugger.command(&mut chip8, "watch 0x310..0x300"), "Empty range '0x310..0x300', type 'help' for a list of commands");
+    assert_eq!(debugger.command(&mut chip8, "watch peek 0x300"), "Unknown watchpoint 'peek 0x300', type 'help' for a list of commands");
+}
+
+#[test]
+fn sprite_reads() {
+    // a watchpoint on a sprite pauses on the draw that reads it, but not on one that draws other bytes
+    let program = [0xA2, 0x0C, 0xD0, 0x01, 0xA2, 0x0A, 0xD0, 0x02, 0x12, 0x08, 0xFF, 0xFF, 0xF0, 0x00];
+    let (mut chip8, mut debugger) = debugger(&program);
+    debugger.command(&mut chip8, "watch 0x20B");
+    run(&mut chip8, &mut debugger);
+    assert_eq!(chip8.pc, 0x208);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (access 0x20B..0x20C): DXYN at 0x206 read 0x20A..0x20C");
+}
+
+#[test]
+fn partial_frames() {
+    // a short frame stops after its cycles without ticking the timers, breakpoints still pause it halfway
+    let (mut chip8, mut debugger) = debugger(&PROGRAM);
+    chip8.delay_timer = 5;
+    debugger.command(&mut chip8, "break 0x204");
+    assert!(debugger.run_cycles(&mut chip8, 3).unwrap());
+    assert_eq!(chip8.pc, 0x204);
+    debugger.resume();
+    assert!(!debugger.run_cycles(&mut chip8, 3).unwrap());
+    assert_eq!((chip8.pc, chip8.vregisters[3], chip8.delay_timer), (0x200, 1, 5));
+
+    // the rest of the frame runs 5 more cycles and then ticks the timers
+    debugger.command(&mut chip8, "delete 0x204");
+    assert!(!debugger.run_frame(&mut chip8).unwrap());
+    assert_eq!((chip8.pc, chip8.vregisters[3], chip8.delay_timer), (0x204, 3, 4));
+}
+
+#[test]
+fn changes_by_hand() {
+    // stepping and editing the state move the baseline the watchpoints compare against, so they don't pause later
+    let (mut chip8, mut debugger) = debugger(&PROGRAM);
+    debugger.command(&mut chip8, "watch v3");
+    debugger.command(&mut chip8, "watch if v3 == 5");
+    debugger.command(&mut chip8, "watch if mem[0x300] == 7");
+    assert!(debugger.command(&mut chip8, "step 3").starts_with("Watchpoint 1 (v3): 00 -> 01 after 0x200\n"));
+    assert_eq!(chip8.pc, 0x202);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (v3): 00 -> 01 after 0x200");
+    debugger.command(&mut chip8, "set v3 5");
+    debugger.command(&mut chip8, "poke 0x300 7");
+    debugger.resume();
+    run(&mut chip8, &mut debugger);
+    assert_eq!(debugger.stop_message(&chip8), "Watchpoint 1 (v3): 05 -> 06 after 0x200");
+}